@@ -0,0 +1,87 @@
+//! Datagrams per second over loopback: one system call per datagram vs. batched
+//!
+//! Run with `cargo bench --features bench --bench batch_io`.
+
+use shoreline_dht::{Batch, recv_batch, send_batch};
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use tokio::time::{Duration, timeout};
+
+const DATAGRAMS: usize = 200_000;
+const DATAGRAM_LEN: usize = 256;
+const SOCKET_BUFFER_SIZE: usize = 8 << 20;
+const IDLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+async fn socket() -> UdpSocket {
+    let sock = UdpSocket::bind("[::1]:0").await.unwrap();
+    let sock2 = socket2::SockRef::from(&sock);
+    sock2.set_recv_buffer_size(SOCKET_BUFFER_SIZE).unwrap();
+    sock2.set_send_buffer_size(SOCKET_BUFFER_SIZE).unwrap();
+    sock
+}
+
+fn addr(sock: &UdpSocket) -> std::net::SocketAddrV6 {
+    match sock.local_addr().unwrap() {
+        SocketAddr::V6(addr) => addr,
+        SocketAddr::V4(_) => unreachable!(),
+    }
+}
+
+/// Send and receive [DATAGRAMS] with `send_to`/`recv_from`
+async fn single() -> (usize, Duration) {
+    let (tx, rx) = (socket().await, socket().await);
+    let dst = addr(&rx);
+    let start = Instant::now();
+    let receiver = tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        let mut n = 0;
+        while let Ok(Ok(_)) = timeout(IDLE_TIMEOUT, rx.recv_from(&mut buf)).await {
+            n += 1;
+        }
+        n
+    });
+    let buf = [0u8; DATAGRAM_LEN];
+    for _ in 0..DATAGRAMS {
+        tx.send_to(&buf, dst).await.unwrap();
+    }
+    let n = receiver.await.unwrap();
+    (n, start.elapsed().saturating_sub(IDLE_TIMEOUT))
+}
+
+/// Send and receive [DATAGRAMS] with [send_batch]/[recv_batch]
+async fn batched() -> (usize, Duration) {
+    let (tx, rx) = (socket().await, socket().await);
+    let dst = addr(&rx);
+    let start = Instant::now();
+    let receiver = tokio::spawn(async move {
+        let mut batch = Batch::new();
+        let mut n = 0;
+        while let Ok(Ok(len)) = timeout(IDLE_TIMEOUT, recv_batch(&rx, &mut batch)).await {
+            n += len;
+        }
+        n
+    });
+    let buf = [0u8; DATAGRAM_LEN];
+    let mut batch = Batch::new();
+    let mut sent = 0;
+    while sent < DATAGRAMS {
+        while batch.push(dst, &buf) {}
+        sent += send_batch(&tx, &mut batch).await.unwrap();
+    }
+    let n = receiver.await.unwrap();
+    (n, start.elapsed().saturating_sub(IDLE_TIMEOUT))
+}
+
+fn report(name: &str, (n, elapsed): (usize, Duration)) -> f64 {
+    let pps = n as f64 / elapsed.as_secs_f64();
+    println!("{:<8} {:>8} datagrams in {:>6.1}ms: {:>10.0}/s", name, n, elapsed.as_secs_f64() * 1000.0, pps);
+    pps
+}
+
+fn main() {
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    let single = report("single", rt.block_on(single()));
+    let batched = report("batched", rt.block_on(batched()));
+    println!("speedup  {:.2}x", batched / single);
+}
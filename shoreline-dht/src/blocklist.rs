@@ -0,0 +1,243 @@
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A list of blocked address ranges (e.g. known monitoring nodes)
+///
+/// Each line is either in P2P plaintext format (`description:first-last`),
+/// ipfilter.dat format (`first - last , level , description`; only levels up
+/// to 127 block) or a CIDR prefix (`2001:db8::/32`). IPv4 addresses are mapped
+/// into `::ffff:0:0/96`. Empty lines and comments (`#`) are skipped, and so are
+/// lines that fail to parse (see [Self::skipped]).
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    name: String,
+    ranges: Vec<(u128, u128)>,
+    skipped: usize,
+    hits: AtomicU64,
+}
+
+impl Blocklist {
+    const IPFILTER_LEVEL_MAX: u32 = 127;
+
+    pub fn parse(name: impl Into<String>, text: &str) -> Self {
+        let mut ranges = vec![];
+        let mut skipped = 0;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+            match Self::parse_line(line) {
+                Some(Some(range)) => ranges.push(range),
+                Some(None) => (),
+                None => skipped += 1,
+            }
+        }
+        ranges.sort_unstable();
+        let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+        for (first, last) in ranges {
+            match merged.last_mut() {
+                Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+                _ => merged.push((first, last)),
+            }
+        }
+        Self { name: name.into(), ranges: merged, skipped, hits: AtomicU64::new(0) }
+    }
+
+    /// Parse a range (or `Some(None)` for an ipfilter.dat line that allows the range)
+    fn parse_line(line: &str) -> Option<Option<(u128, u128)>> {
+        // P2P descriptions may contain commas, so only take lines with a range
+        // and a level as ipfilter.dat
+        let mut cols = line.split(',').map(str::trim);
+        if let Some(range) = cols.next().and_then(Self::parse_range)
+            && let Some(Ok(level)) = cols.next().map(str::parse::<u32>)
+        {
+            return Some((level <= Self::IPFILTER_LEVEL_MAX).then_some(range));
+        }
+        if let Some((addr, len)) = line.split_once('/') {
+            let (addr, len) = (parse_addr(addr.trim())?, len.trim().parse::<u32>().ok()?);
+            let len = if addr.to_ipv4_mapped().is_some() {
+                len.checked_add(96)?
+            } else {
+                len
+            };
+            if len > 128 {
+                return None;
+            }
+            let host = u128::MAX.checked_shr(len).unwrap_or(0);
+            let first = addr.to_bits() & !host;
+            return Some(Some((first, first | host)));
+        }
+        if line.contains('-') {
+            // The description may contain colons, so try each one as separator
+            let (desc, range) = line.rsplit_once('-')?;
+            let last = range.trim();
+            let first = desc.match_indices(':').find_map(|(i, _)| parse_addr(desc[i + 1..].trim()));
+            let first = first.or_else(|| parse_addr(desc.trim()))?;
+            let last = parse_addr(last)?;
+            return Some((first <= last).then_some((first.to_bits(), last.to_bits())));
+        }
+        let addr = parse_addr(line)?.to_bits();
+        Some(Some((addr, addr)))
+    }
+
+    fn parse_range(s: &str) -> Option<(u128, u128)> {
+        let (first, last) = s.split_once('-')?;
+        let (first, last) = (parse_addr(first.trim())?, parse_addr(last.trim())?);
+        (first <= last).then_some((first.to_bits(), last.to_bits()))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the number of (merged) ranges
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Get the number of lines that failed to parse
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Get the number of addresses blocked by this list so far
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn contains(&self, ip: &Ipv6Addr) -> bool {
+        let ip = ip.to_bits();
+        let i = self.ranges.partition_point(|(first, _)| *first <= ip);
+        i > 0 && ip <= self.ranges[i - 1].1
+    }
+}
+
+impl Display for Blocklist {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} ranges, {} hits", self.name, self.len(), self.hits())
+    }
+}
+
+/// All [Blocklist]s in effect
+#[derive(Debug, Default)]
+pub struct Blocklists(Vec<Blocklist>);
+
+impl Blocklists {
+    pub fn new(lists: Vec<Blocklist>) -> Self {
+        Self(lists)
+    }
+
+    /// Check whether `ip` is blocked and count the hit on the first matching list
+    pub fn blocks(&self, ip: &Ipv6Addr) -> bool {
+        match self.0.iter().find(|l| l.contains(ip)) {
+            Some(list) => {
+                list.hits.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Check whether `ip` is on any list without counting a hit
+    ///
+    /// For internal checks of addresses already counted (or not received from).
+    pub fn contains(&self, ip: &Ipv6Addr) -> bool {
+        self.0.iter().any(|l| l.contains(ip))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Blocklist> {
+        self.0.iter()
+    }
+
+    /// Get the total number of hits of all lists
+    pub fn hits(&self) -> u64 {
+        self.0.iter().map(Blocklist::hits).sum()
+    }
+}
+
+/// Parse an IPv6 or IPv4 address (the latter possibly zero-padded as in ipfilter.dat)
+fn parse_addr(s: &str) -> Option<Ipv6Addr> {
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Some(match ip {
+            IpAddr::V6(ip) => ip,
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        });
+    }
+    let mut octets = [0u8; 4];
+    let mut parts = s.split('.');
+    for octet in octets.iter_mut() {
+        let part = parts.next().filter(|p| !p.is_empty() && p.len() <= 3)?;
+        *octet = part.parse().ok()?;
+    }
+    parts.next().is_none().then(|| Ipv4Addr::from(octets).to_ipv6_mapped())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(list: &Blocklist, ip: &str) -> bool {
+        list.contains(&parse_addr(ip).unwrap())
+    }
+
+    #[test]
+    fn parses_p2p_format() {
+        let list =
+            Blocklist::parse("p2p", "# comment\nSome org: inc:2001:db8::10-2001:db8::1f\nv4:1.2.3.0-1.2.3.255\n");
+        assert_eq!((list.len(), list.skipped()), (2, 0));
+        assert!(blocks(&list, "2001:db8::10") && blocks(&list, "2001:db8::1f"));
+        assert!(!blocks(&list, "2001:db8::20") && !blocks(&list, "2001:db8::f"));
+        assert!(blocks(&list, "1.2.3.4"));
+    }
+
+    #[test]
+    fn parses_p2p_format_with_commas() {
+        let text = "Level 3, Inc:4.0.0.0-4.255.255.255\nFoo, 100, bar:2001:db8::1-2001:db8::1\n";
+        let list = Blocklist::parse("p2p", text);
+        assert_eq!((list.len(), list.skipped()), (2, 0));
+        assert!(blocks(&list, "4.1.2.3") && blocks(&list, "2001:db8::1"));
+        assert!(!blocks(&list, "5.0.0.0"));
+    }
+
+    #[test]
+    fn parses_ipfilter_format() {
+        let text = "001.002.003.000 - 001.002.003.255 , 000 , blocked\n\
+                    2001:db8:: - 2001:db8::ffff , 100 , blocked v6\n\
+                    005.006.007.008 - 005.006.007.008 , 200 , allowed\n";
+        let list = Blocklist::parse("ipfilter.dat", text);
+        assert_eq!((list.len(), list.skipped()), (2, 0));
+        assert!(blocks(&list, "1.2.3.9") && blocks(&list, "2001:db8::abcd"));
+        assert!(!blocks(&list, "5.6.7.8"));
+    }
+
+    #[test]
+    fn parses_cidr_format_and_merges() {
+        let text = "2001:db8::/32\n2001:db8:1::/48\n2001:db9::/32\n10.0.0.0/8\n::1\nnonsense\n2001:db8::/129\n";
+        let list = Blocklist::parse("cidr", text);
+        assert_eq!((list.len(), list.skipped()), (3, 2));
+        assert!(blocks(&list, "2001:db9:ffff::1") && blocks(&list, "10.255.0.1") && blocks(&list, "::1"));
+        assert!(!blocks(&list, "2001:dba::") && !blocks(&list, "11.0.0.0"));
+    }
+
+    #[test]
+    fn counts_hits_on_first_match() {
+        let lists = Blocklists::new(vec![Blocklist::parse("a", "::1"), Blocklist::parse("b", "::/0")]);
+        assert!(lists.blocks(&Ipv6Addr::LOCALHOST));
+        assert!(lists.blocks(&Ipv6Addr::UNSPECIFIED));
+        assert_eq!(lists.iter().map(Blocklist::hits).collect::<Vec<_>>(), vec![1, 1]);
+        assert_eq!(lists.hits(), 2);
+    }
+
+    #[test]
+    fn contains_does_not_count_hits() {
+        let lists = Blocklists::new(vec![Blocklist::parse("a", "::1")]);
+        assert!(lists.contains(&Ipv6Addr::LOCALHOST));
+        assert!(!lists.contains(&Ipv6Addr::UNSPECIFIED));
+        assert_eq!(lists.hits(), 0);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::Info;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::net::SocketAddrV6;
+use tokio::sync::watch;
+
+/// Shared bootstrap state of all [Node](crate::Node)s
+///
+/// Combines the seed addresses, the persisted nodes used as fallback when no
+/// seed responds and the readiness flag set by the first bootstrapped node.
+#[derive(Debug, Clone)]
+pub struct Bootstrap {
+    seeds: watch::Receiver<Vec<SocketAddrV6>>,
+    cache: watch::Sender<Vec<Info>>,
+    ready: watch::Sender<bool>,
+}
+
+impl Bootstrap {
+    pub fn new(seeds: watch::Receiver<Vec<SocketAddrV6>>) -> Self {
+        let cache = watch::channel(Vec::new()).0;
+        let ready = watch::channel(false).0;
+        Self { seeds, cache, ready }
+    }
+
+    pub fn seeds(&self) -> &watch::Receiver<Vec<SocketAddrV6>> {
+        &self.seeds
+    }
+
+    /// Get the persisted nodes
+    pub fn cache(&self) -> Vec<Info> {
+        self.cache.borrow().clone()
+    }
+
+    /// Set the persisted nodes
+    pub fn set_cache(&self, infos: Vec<Info>) {
+        self.cache.send_replace(infos);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        *self.ready.borrow()
+    }
+
+    /// Mark the DHT as bootstrapped (this cannot be undone)
+    pub fn set_ready(&self) {
+        self.ready.send_if_modified(|r| !std::mem::replace(r, true));
+    }
+
+    /// Wait until the DHT is bootstrapped
+    pub fn ready(&self) -> impl Future<Output = ()> + use<> {
+        let mut ready = self.ready.subscribe();
+        async move {
+            let _ = ready.wait_for(|r| *r).await;
+        }
+    }
+}
+
+/// Pending find_node queries to the seeds (one per seed address)
+///
+/// Only responses matching a pending query count as seed responses.
+#[derive(Debug, Default)]
+pub struct SeedQueries(BTreeMap<SocketAddrV6, [u8; 8]>);
+
+impl SeedQueries {
+    /// Get a fresh transaction ID for a query to `addr` (superseding a pending one)
+    pub fn start(&mut self, addr: SocketAddrV6) -> [u8; 8] {
+        let t = rand::random::<u64>().to_be_bytes();
+        self.0.insert(addr, t);
+        t
+    }
+
+    /// Check whether a query to `addr` is pending
+    pub fn is_pending(&self, addr: &SocketAddrV6) -> bool {
+        self.0.contains_key(addr)
+    }
+
+    /// Check whether `t` from `addr` answers a pending query (without resolving it)
+    pub fn matches(&self, addr: &SocketAddrV6, t: &[u8]) -> bool {
+        self.0.get(addr).is_some_and(|x| x == t)
+    }
+
+    /// Check whether `t` from `addr` answers a pending query (at most once)
+    pub fn resolve(&mut self, addr: &SocketAddrV6, t: &[u8]) -> bool {
+        let pending = self.matches(addr, t);
+        if pending {
+            self.0.remove(addr);
+        }
+        pending
+    }
+}
+
+/// Bootstrap phase of a [Node](crate::Node)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BootstrapState {
+    /// Querying the seeds
+    #[default]
+    Seeding,
+    /// Seeds did not respond; trying the persisted nodes (and seeds)
+    Fallback,
+    /// The routing table contains at least one good node
+    Done,
+}
+
+impl Display for BootstrapState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Seeding => write!(f, "Seeding"),
+            Self::Fallback => write!(f, "Fallback"),
+            Self::Done => write!(f, "Done"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: SocketAddrV6 = SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, 6881, 0, 0);
+
+    #[test]
+    fn resolves_seed_response_once() {
+        let mut queries = SeedQueries::default();
+        let other = SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, 6882, 0, 0);
+        let t = queries.start(SEED);
+        assert!(!queries.resolve(&SEED, &[0]));
+        assert!(!queries.resolve(&other, &t));
+        assert!(queries.resolve(&SEED, &t));
+        assert!(!queries.resolve(&SEED, &t));
+    }
+
+    #[test]
+    fn supersedes_pending_seed_query() {
+        let mut queries = SeedQueries::default();
+        let old = queries.start(SEED);
+        let new = queries.start(SEED);
+        assert!(!queries.resolve(&SEED, &old));
+        assert!(queries.resolve(&SEED, &new));
+    }
+
+    #[test]
+    fn starts_seeding_and_displays_state() {
+        assert_eq!(BootstrapState::default(), BootstrapState::Seeding);
+        let states = [BootstrapState::Seeding, BootstrapState::Fallback, BootstrapState::Done];
+        assert_eq!(states.map(|s| s.to_string()), ["Seeding", "Fallback", "Done"]);
+    }
+
+    #[tokio::test]
+    async fn becomes_ready_once() {
+        let boot = Bootstrap::new(watch::channel(vec![]).1);
+        let ready = boot.ready();
+        assert!(!boot.is_ready());
+        boot.set_ready();
+        boot.set_ready();
+        ready.await;
+        assert!(boot.is_ready());
+    }
+}
@@ -0,0 +1,23 @@
+use crate::util::check;
+use std::net::{Ipv6Addr, SocketAddrV6};
+
+/// Compact encoding of a [SocketAddrV6] (16 bytes address, 2 bytes port)
+pub struct CompactAddr;
+
+impl CompactAddr {
+    pub const BYTES: usize = 18;
+
+    pub fn encode(addr: &SocketAddrV6) -> [u8; Self::BYTES] {
+        let mut buf = [0; Self::BYTES];
+        buf[..16].copy_from_slice(&addr.ip().octets());
+        buf[16..].copy_from_slice(&addr.port().to_be_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<SocketAddrV6> {
+        check(buf.len() == Self::BYTES)?;
+        let ip: [u8; 16] = buf[..16].try_into().ok()?;
+        let pt: [u8; 2] = buf[16..].try_into().ok()?;
+        Some(SocketAddrV6::new(Ipv6Addr::from(ip), u16::from_be_bytes(pt), 0, 0))
+    }
+}
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 
 use bencode_minimal::{TryFromValue, Value};
-use std::{num::ParseIntError, str::FromStr};
+use std::net::Ipv6Addr;
+use std::{fmt::Display, str::FromStr};
 
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct Id([u8; Self::BYTES]);
@@ -10,6 +11,9 @@ impl Id {
     pub const BYTES: usize = 20;
     pub const UNKNOWN: Self = Self([0; Self::BYTES]);
 
+    const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    const BASE32_LEN: usize = Self::BYTES * 8 / 5;
+
     pub fn from_bytes(bytes: &[u8; Self::BYTES]) -> Self {
         Self(*bytes)
     }
@@ -61,9 +65,15 @@ impl Id {
         Self(rand::random())
     }
 
+    /// Get a random [Id] with exactly `n` leading bits in common with `self`
+    ///
+    /// This is an [Id] that falls into bucket `n` of a routing table around `self`.
+    /// For `n >= 160` this is `self`.
     pub fn random_in_bucket(&self, n: usize) -> Self {
+        if n >= Self::BYTES * 8 {
+            return *self;
+        }
         let mut x = Self::random().0;
-        let n = n.min(Self::BYTES * 8);
         let q = n / 8;
         let r = n % 8;
 
@@ -81,9 +91,102 @@ impl Id {
         Self(x)
     }
 
+    /// Derive the [Id] of the node on `ip` from this (host) [Id] (BEP 45)
+    ///
+    /// Each address gets its own stable [Id], spread across the key space
+    /// (FNV-1a over the host [Id] and the address, seeded per 8-byte chunk and
+    /// finalized like SplitMix64 so that adjacent addresses get unrelated IDs).
+    pub fn for_addr(&self, ip: &Ipv6Addr) -> Self {
+        let mut x = [0; Self::BYTES];
+        for (i, chunk) in x.chunks_mut(8).enumerate() {
+            let mut h = 0xcbf29ce484222325u64 ^ i as u64;
+            for b in self.0.iter().chain(&ip.octets()) {
+                h = (h ^ *b as u64).wrapping_mul(0x100000001b3);
+            }
+            h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+            h ^= h >> 31;
+            chunk.copy_from_slice(&h.to_be_bytes()[..chunk.len()]);
+        }
+        Self(x)
+    }
+
     pub fn is_null(&self) -> bool {
         self.0.iter().all(|&x| x == 0)
     }
+
+    /// Encode as unpadded lowercase base32 (RFC 4648), e.g. for contact strings
+    pub fn to_base32(&self) -> String {
+        let mut s = String::with_capacity(Self::BASE32_LEN);
+        let (mut acc, mut bits) = (0u32, 0);
+        for &b in self.0.iter() {
+            acc = (acc << 8) | b as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                s.push(Self::BASE32[(acc >> bits) as usize & 31] as char);
+            }
+        }
+        s
+    }
+
+    /// Decode from unpadded base32 (RFC 4648, case-insensitive)
+    pub fn from_base32(s: &str) -> Result<Self, ParseIdError> {
+        check_ascii_len(s, Self::BASE32_LEN)?;
+        let mut x = [0; Self::BYTES];
+        let (mut acc, mut bits, mut n) = (0u32, 0, 0);
+        for (i, c) in s.bytes().enumerate() {
+            let v = match c.to_ascii_lowercase() {
+                c @ b'a'..=b'z' => c - b'a',
+                c @ b'2'..=b'7' => c - b'2' + 26,
+                _ => return Err(ParseIdError::NonBase32(i)),
+            };
+            acc = (acc << 5) | v as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                x[n] = (acc >> bits) as u8;
+                n += 1;
+            }
+        }
+        Ok(Self(x))
+    }
+}
+
+/// Error when parsing an [Id] from a string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseIdError {
+    /// The string does not have the expected number of characters
+    Length(usize),
+    /// The character at the given position is not a hex digit
+    NonHex(usize),
+    /// The character at the given position is not a base32 digit
+    NonBase32(usize),
+    /// The string contains non-ASCII characters
+    NonAscii,
+}
+
+impl Display for ParseIdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Length(n) => write!(f, "ID has invalid length {}", n),
+            Self::NonHex(i) => write!(f, "ID has non-hex character at position {}", i),
+            Self::NonBase32(i) => write!(f, "ID has non-base32 character at position {}", i),
+            Self::NonAscii => write!(f, "ID contains non-ASCII characters"),
+        }
+    }
+}
+
+impl std::error::Error for ParseIdError {}
+
+fn check_ascii_len(s: &str, len: usize) -> Result<(), ParseIdError> {
+    if !s.is_ascii() {
+        Err(ParseIdError::NonAscii)
+    } else if s.len() != len {
+        Err(ParseIdError::Length(s.len()))
+    } else {
+        Ok(())
+    }
 }
 
 impl AsRef<[u8]> for Id {
@@ -98,15 +201,17 @@ impl<'a> TryFromValue<'a> for Id {
     }
 }
 
+/// Parse from exactly 40 hex digits (case-insensitive)
 impl FromStr for Id {
-    type Err = ParseIntError;
+    type Err = ParseIdError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut x = [0; 20];
-        for (a, b) in (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16)).zip(x.iter_mut()) {
-            *b = a?;
+        check_ascii_len(s, 2 * Self::BYTES)?;
+        let mut x = [0; Self::BYTES];
+        for (i, c) in s.chars().enumerate() {
+            let d = c.to_digit(16).ok_or(ParseIdError::NonHex(i))? as u8;
+            x[i / 2] = (x[i / 2] << 4) | d;
         }
-
         Ok(Self(x))
     }
 }
@@ -159,3 +264,111 @@ impl<'de> Deserialize<'de> for Id {
         Id::from_str(&s).map_err(serde::de::Error::custom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const BITS: usize = Id::BYTES * 8;
+
+    fn id() -> impl Strategy<Value = Id> {
+        any::<[u8; Id::BYTES]>().prop_map(|x| Id::from_bytes(&x))
+    }
+
+    #[test]
+    fn derives_distinct_ids_per_addr() {
+        let host = Id::random();
+        let (a, b) = ("2001:db8::1".parse().unwrap(), "2001:db8::2".parse().unwrap());
+        assert_eq!(host.for_addr(&a), host.for_addr(&a));
+        assert_ne!(host.for_addr(&a), host.for_addr(&b));
+        assert_ne!(host.for_addr(&a), Id::random().for_addr(&a));
+        assert!(host.for_addr(&a).similarity(&host.for_addr(&b)) < 32);
+    }
+
+    proptest! {
+        #[test]
+        fn similarity_is_reflexive(a in id()) {
+            prop_assert_eq!(a.similarity(&a), BITS);
+            prop_assert_eq!(a.distance(&a), 0);
+        }
+
+        #[test]
+        fn similarity_is_symmetric(a in id(), b in id()) {
+            prop_assert_eq!(a.similarity(&b), b.similarity(&a));
+            prop_assert_eq!(a.distance(&b), b.distance(&a));
+        }
+
+        #[test]
+        fn similarity_is_leading_zeros_of_xor(a in id(), b in id()) {
+            prop_assert_eq!(a.similarity(&b), a.xor(&b).similarity(&Id::UNKNOWN));
+            prop_assert_eq!(a.distance(&b) == 0, a == b);
+        }
+
+        #[test]
+        fn distance_is_ultrametric(a in id(), b in id(), c in id()) {
+            prop_assert!(a.distance(&c) <= a.distance(&b).max(b.distance(&c)));
+        }
+
+        #[test]
+        fn xor_is_involutive(a in id(), b in id()) {
+            prop_assert_eq!(a.xor(&b).xor(&b), a);
+            prop_assert_eq!(a.xor(&a), Id::UNKNOWN);
+        }
+
+        #[test]
+        fn not_is_involutive_and_maximally_distant(a in id()) {
+            prop_assert_eq!(a.not().not(), a);
+            prop_assert_eq!(a.distance(&a.not()), BITS);
+        }
+
+        #[test]
+        fn random_in_bucket_is_in_bucket(a in id()) {
+            for n in 0..=BITS {
+                let b = a.random_in_bucket(n);
+                prop_assert_eq!(a.similarity(&b), n, "bucket {}", n);
+            }
+        }
+
+        #[test]
+        fn display_from_str_roundtrip(a in id()) {
+            let s = a.to_string();
+            prop_assert_eq!(s.len(), 2 * Id::BYTES);
+            prop_assert_eq!(Id::from_str(&s).unwrap(), a);
+            prop_assert_eq!(Id::from_str(&s.to_uppercase()).unwrap(), a);
+        }
+
+        #[test]
+        fn base32_roundtrip(a in id()) {
+            let s = a.to_base32();
+            prop_assert_eq!(s.len(), 32);
+            prop_assert_eq!(Id::from_base32(&s).unwrap(), a);
+            prop_assert_eq!(Id::from_base32(&s.to_uppercase()).unwrap(), a);
+        }
+
+        #[test]
+        fn from_str_rejects_wrong_length(s in "[0-9a-f]{0,80}") {
+            prop_assume!(s.len() != 40);
+            prop_assert_eq!(Id::from_str(&s), Err(ParseIdError::Length(s.len())));
+        }
+    }
+
+    #[test]
+    fn from_str_rejects_invalid_input() {
+        let s = "49f9a535d8878a6ac328ae42e176e3a9526d9604";
+        assert!(Id::from_str(s).is_ok());
+        assert_eq!(Id::from_str(&s[..39]), Err(ParseIdError::Length(39)));
+        assert_eq!(Id::from_str(&s[..38]), Err(ParseIdError::Length(38)));
+        assert_eq!(Id::from_str(&s.replace('5', "g")), Err(ParseIdError::NonHex(5)));
+        assert_eq!(Id::from_str(&s.replacen("49", "\u{e4}", 1)), Err(ParseIdError::NonAscii));
+        assert_eq!(Id::from_str("+9f9a535d8878a6ac328ae42e176e3a9526d9604"), Err(ParseIdError::NonHex(0)));
+    }
+
+    #[test]
+    fn from_base32_rejects_invalid_input() {
+        assert_eq!(Id::UNKNOWN.to_base32(), "a".repeat(32));
+        assert_eq!(Id::from_base32(&"a".repeat(31)), Err(ParseIdError::Length(31)));
+        assert_eq!(Id::from_base32(&format!("{}1", "a".repeat(31))), Err(ParseIdError::NonBase32(31)));
+        assert_eq!(Id::from_base32(&format!("{}\u{e4}", "a".repeat(30))), Err(ParseIdError::NonAscii));
+    }
+}
@@ -1,10 +1,12 @@
+mod addr;
 mod id;
 mod info;
 mod infos;
 mod msg;
 mod version;
 
-pub use self::id::Id;
+pub use self::addr::CompactAddr;
+pub use self::id::{Id, ParseIdError};
 pub use self::info::Info;
 pub use self::infos::Infos;
 pub use self::msg::Msg;
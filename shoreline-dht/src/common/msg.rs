@@ -2,6 +2,10 @@ use super::Id;
 use bencode_minimal::{Value, dict, int, list, str};
 use super::Version;
 
+/// Builders for KRPC messages
+///
+/// All responses (and errors) contain the compact address of the requester as
+/// observed by us in the `ip` key.
 pub struct Msg;
 
 impl Msg {
@@ -13,6 +17,7 @@ impl Msg {
     pub const A: &str = "a";
     pub const V: &str = "v";
     pub const ID: &str = "id";
+    pub const IP: &str = "ip";
     pub const PING: &str = "ping";
     pub const TOKEN: &str = "token";
     pub const TOKEN_VALUE: &str = "NONE";
@@ -22,10 +27,25 @@ impl Msg {
     pub const GET_PEERS: &str = "get_peers";
     pub const ANNOUNCE_PEER: &str = "announce_peer";
     pub const NODES6: &str = "nodes6";
+    pub const PROBE: &str = "sl_probe";
 
-    pub fn error_204<'a>(t: &'a [u8]) -> Value<'a> {
+    pub fn error_202<'a>(t: &'a [u8], ip: &'a [u8]) -> Value<'a> {
         dict! {
             Msg::T => str!(t),
+            Msg::IP => str!(ip),
+            Msg::V => str!(Version::SELF.as_ref()),
+            Msg::Y => str!(Msg::E),
+            Msg::E => list![
+                int!(202),
+                str!("Server Error"),
+            ],
+        }
+    }
+
+    pub fn error_204<'a>(t: &'a [u8], ip: &'a [u8]) -> Value<'a> {
+        dict! {
+            Msg::T => str!(t),
+            Msg::IP => str!(ip),
             Msg::V => str!(Version::SELF.as_ref()),
             Msg::Y => str!(Msg::E),
             Msg::E => list![
@@ -47,9 +67,35 @@ impl Msg {
         }
     }
 
-    pub fn ping_response<'a>(t: &'a [u8], id: &'a Id) -> Value<'a> {
+    pub fn ping_response<'a>(t: &'a [u8], ip: &'a [u8], id: &'a Id) -> Value<'a> {
+        dict! {
+            Msg::T => str!(t),
+            Msg::IP => str!(ip),
+            Msg::V => str!(Version::SELF.as_ref()),
+            Msg::Y => str!(Msg::R),
+            Msg::R => dict! {
+                Msg::ID => str!(id),
+            }
+        }
+    }
+
+    /// Ask a shoreline peer to query us from another port (reachability check)
+    pub fn probe_query<'a>(t: &'a [u8], id: &'a Id) -> Value<'a> {
+        dict! {
+            Msg::T => str!(t),
+            Msg::V => str!(Version::SELF.as_ref()),
+            Msg::Y => str!(Msg::Q),
+            Msg::Q => str!(Msg::PROBE),
+            Msg::A => dict! {
+                Msg::ID => str!(id),
+            }
+        }
+    }
+
+    pub fn probe_response<'a>(t: &'a [u8], ip: &'a [u8], id: &'a Id) -> Value<'a> {
         dict! {
             Msg::T => str!(t),
+            Msg::IP => str!(ip),
             Msg::V => str!(Version::SELF.as_ref()),
             Msg::Y => str!(Msg::R),
             Msg::R => dict! {
@@ -71,9 +117,10 @@ impl Msg {
         }
     }
 
-    pub fn find_node_response<'a>(t: &'a [u8], id: &'a Id, nodes6: &'a [u8]) -> Value<'a> {
+    pub fn find_node_response<'a>(t: &'a [u8], ip: &'a [u8], id: &'a Id, nodes6: &'a [u8]) -> Value<'a> {
         dict! {
             Msg::T => str!(t),
+            Msg::IP => str!(ip),
             Msg::V => str!(Version::SELF.as_ref()),
             Msg::Y => str!(Msg::R),
             Msg::R => dict! {
@@ -83,9 +130,10 @@ impl Msg {
         }
     }
 
-    pub fn get_peers_response<'a>(t: &'a [u8], id: &'a Id, token: &'a [u8], nodes6: &'a [u8]) -> Value<'a> {
+    pub fn get_peers_response<'a>(t: &'a [u8], ip: &'a [u8], id: &'a Id, token: &'a [u8], nodes6: &'a [u8]) -> Value<'a> {
         dict! {
             Msg::T => str!(t),
+            Msg::IP => str!(ip),
             Msg::V => str!(Version::SELF.as_ref()),
             Msg::Y => str!(Msg::R),
             Msg::R => dict! {
@@ -96,9 +144,10 @@ impl Msg {
         }
     }
 
-    pub fn announce_peer_response<'a>(t: &'a [u8], id: &'a Id) -> Value<'a> {
+    pub fn announce_peer_response<'a>(t: &'a [u8], ip: &'a [u8], id: &'a Id) -> Value<'a> {
         dict! {
             Msg::T => str!(t),
+            Msg::IP => str!(ip),
             Msg::V => str!(Version::SELF.as_ref()),
             Msg::Y => str!(Msg::R),
             Msg::R => dict! {
@@ -9,6 +9,11 @@ impl Version {
 
     const SELF_MAJOR: u8 = env!("CARGO_PKG_VERSION_MAJOR").as_bytes()[0] - b'0';
     const SELF_MINOR: u8 = env!("CARGO_PKG_VERSION_MINOR").as_bytes()[0] - b'0';
+
+    /// Whether the client is shoreline (any version)
+    pub fn is_shoreline(&self) -> bool {
+        self.0[..2] == Self::SELF.0[..2]
+    }
 }
 
 impl AsRef<[u8]> for Version {
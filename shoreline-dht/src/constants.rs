@@ -1,14 +1,69 @@
 use tokio::time::Duration;
 
-pub const TIMEOUT_FACTOR: f32 = 3.0;
 pub const TIMEOUT_INIT: Duration = Duration::from_secs(10);
 pub const TIMEOUT_TOTAL: Duration = Duration::from_secs(300);
+pub const RTO_MIN: Duration = Duration::from_millis(250);
+pub const RTO_MAX: Duration = Duration::from_secs(10);
 
 pub const RBUF_SIZE: usize = 1500;
-pub const PING_INTERVAL: Duration = Duration::from_secs(25);
+pub(crate) const IO_BATCH_SIZE: usize = 32;
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(25);
 pub const PING_STARTUP_DELAY: Duration = Duration::from_millis(10);
 pub const LINK_REMOVAL_DELAY: Duration = Duration::from_secs(5);
+pub const LINK_QUESTIONABLE_AFTER: Duration = Duration::from_secs(900);
+pub const LINK_RETRY_INTERVAL: Duration = Duration::from_secs(15);
+pub const LINK_FAILURES_MAX: u32 = 3;
 pub const BENCODE_MAX_ALLOCS: usize = 20;
 
+pub const NODE_CMDS_MAX: usize = 1024;
+pub const NODE_SEND_QUEUE_MAX: usize = 1024;
+pub const NODE_MIGRATION_GRACE: Duration = Duration::from_secs(30);
+pub const NODE_MIGRATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+pub const LINK_CMDS_MAX: usize = 64;
+pub const LINK_QUEUE_MAX: usize = 64;
+pub const LINK_QUERIES_MAX: usize = 16;
+pub const PEER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
 pub const BUCKET_MAX_LEN: usize = 8;
+pub const FIND_NODE_MAX_LEN: usize = 8;
+pub const BUCKET_MAX_PER_64: usize = 2;
+pub const BUCKET_MAX_PER_48: usize = 4;
+pub const TABLE_MAX_PER_64: usize = 8;
+pub const TABLE_MAX_PER_48: usize = 16;
+
+pub const CLUSTER_IDS_MAX: usize = 64;
+pub const CLUSTER_ENTRIES_MAX: usize = 8192;
+pub const CLUSTER_EXPIRY: Duration = Duration::from_secs(1800);
+pub const CLUSTERS_MAX: usize = 16;
+
+pub const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(5);
+pub const BOOTSTRAP_SEED_ATTEMPTS: u32 = 3;
+
+pub const SEEDS_RESOLVE_INTERVAL: Duration = Duration::from_secs(1800);
+
+pub const EXTERNAL_VOTES_MIN: usize = 2;
+pub const EXTERNAL_VOTES_MAX: usize = 64;
+
+pub const REACHABILITY_INTERVAL: Duration = Duration::from_secs(60);
+pub const REACHABILITY_WINDOW: Duration = Duration::from_secs(1800);
+pub const REACHABILITY_CONTACT_EXPIRY: Duration = Duration::from_secs(300);
+pub const REACHABILITY_PROBE_GRACE: Duration = Duration::from_secs(5);
+pub const REACHABILITY_PROBES: u32 = 3;
+
+pub const THROTTLE_ADDR_RATE: f32 = 5.0;
+pub const THROTTLE_ADDR_BURST: f32 = 20.0;
+pub const THROTTLE_PREFIX_RATE: f32 = 20.0;
+pub const THROTTLE_PREFIX_BURST: f32 = 80.0;
+pub const THROTTLE_BAN_STRIKES: u32 = 50;
+pub const THROTTLE_BAN_DURATION: Duration = Duration::from_secs(600);
+pub const THROTTLE_ENTRIES_MAX: usize = 8192;
+pub const THROTTLE_LOOKUP_RATE: f32 = 200.0;
+pub const THROTTLE_LOOKUP_BURST: f32 = 400.0;
+
+pub const PCP_SERVER_PORT: u16 = 5351;
+pub const PCP_LIFETIME: Duration = Duration::from_secs(7200);
+pub const PCP_TIMEOUT: Duration = Duration::from_millis(250);
+pub const PCP_ATTEMPTS: u32 = 4;
+pub const PCP_RENEW_MIN: Duration = Duration::from_secs(60);
+pub const PCP_RETRY_INTERVAL: Duration = Duration::from_secs(300);
@@ -1,8 +1,16 @@
 use crate::Id;
+use crate::blocklist::Blocklists;
+use crate::Info;
+use crate::bootstrap::Bootstrap;
 use crate::Node;
 use crate::Nodes;
 use crate::Peers;
+use crate::Policy;
+use crate::PortMapping;
+use crate::SocketOptions;
+use crate::limits::{Limits, ResourceUsage};
 use crate::peer::Peer;
+use std::future::Future;
 use std::net::SocketAddrV6;
 use std::{collections::BTreeMap, sync::Arc};
 use tokio::sync::watch;
@@ -15,24 +23,90 @@ pub struct DHT {
     id: Id,
     peers: Peers,
     nodes: Nodes,
+    policy: Policy,
+    boot: Bootstrap,
     #[allow(dead_code)]
     guard: DropGuard,
 }
 
 impl DHT {
     /// Create a new [Node] node with the given [NodeInfo]
-    pub fn new(id: Id, port: u16, seeds: watch::Receiver<Vec<SocketAddrV6>>) -> Self {
+    pub fn new(
+        id: Id,
+        port: u16,
+        policy: Policy,
+        mapping: PortMapping,
+        opts: SocketOptions,
+        limits: Limits,
+        seeds: watch::Receiver<Vec<SocketAddrV6>>,
+    ) -> Self {
         let token = CancellationToken::new();
-        let peers = Peers::new(token.clone());
-        let nodes = Nodes::new(id, port, peers.clone(), seeds);
+        let peers = Peers::new(token.clone(), limits);
+        let boot = Bootstrap::new(seeds);
+        let nodes = Nodes::new(id, port, policy.clone(), mapping, opts, peers.clone(), boot.clone());
         let guard = token.drop_guard();
-        Self { id, peers, nodes, guard }
+        Self { id, peers, nodes, policy, boot, guard }
     }
 
+    /// Get the host [Id] from which the [Id]s of the nodes are derived (see [Id::for_addr])
     pub fn id(&self) -> &Id {
         &self.id
     }
 
+    /// Get the [Policy] deciding which interfaces are used
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Check whether any node has completed bootstrapping
+    pub fn is_bootstrapped(&self) -> bool {
+        self.boot.is_ready()
+    }
+
+    /// Wait until any node has completed bootstrapping
+    ///
+    /// A node is bootstrapped once its routing table contains a good node.
+    pub fn bootstrapped(&self) -> impl Future<Output = ()> + use<> {
+        self.boot.ready()
+    }
+
+    /// Provide previously persisted nodes to bootstrap from if the seeds are unresponsive
+    pub fn restore(&self, infos: Vec<Info>) {
+        self.boot.set_cache(infos);
+    }
+
+    /// Get the good nodes currently known, e.g. for persisting them
+    pub fn snapshot(&self) -> Vec<Info> {
+        let mut infos = vec![];
+        for peer in self.peers.borrow().values() {
+            for link in peer.links().values().filter(|l| l.stat().borrow().status.is_good()) {
+                infos.push(Info::new(*peer.id(), *link.addr()));
+            }
+        }
+        infos
+    }
+
+    /// Replace the [Blocklists] (links to blocked addresses are terminated)
+    pub fn set_blocklists(&self, lists: Blocklists) {
+        self.peers.set_blocklists(lists);
+    }
+
+    /// Get the [Blocklists] in effect (e.g. for their hit counts)
+    pub fn blocklists(&self) -> Arc<Blocklists> {
+        self.peers.blocklists()
+    }
+
+    /// Report the resources currently in use (see [Limits])
+    pub fn resource_usage(&self) -> ResourceUsage {
+        let peers = self.peers.borrow();
+        ResourceUsage {
+            tasks: self.peers.tasks().count(),
+            peers: peers.len(),
+            links: peers.values().map(|p| p.links().len()).sum(),
+            sockets: self.nodes.borrow().values().map(|n| n.sockets()).sum(),
+        }
+    }
+
     pub fn peers(&self) -> impl std::ops::Deref<Target = BTreeMap<Id, Arc<Peer>>> + '_ {
         self.peers.borrow()
     }
@@ -1,12 +1,18 @@
 use std::fmt;
+use crate::Id;
 use crate::constants::*;
 
 #[derive(Debug)]
 pub enum Error {
     NodeTerminated,
     LinkTerminated,
+    PeerUnreachable,
+    Overloaded,
+    LimitExceeded,
+    Blocked,
     IdMissing,
     IdMismatch,
+    IdChanged(Id),
     InitTimeout,
     TotalTimeout,
     QueryTimeout,
@@ -21,10 +27,15 @@ impl fmt::Display for Error {
         match self {
             Self::NodeTerminated => write!(f, "Node terminated"),
             Self::LinkTerminated => write!(f, "Link terminated"),
+            Self::PeerUnreachable => write!(f, "No usable link to peer"),
+            Self::Overloaded => write!(f, "Overloaded"),
+            Self::LimitExceeded => write!(f, "Resource limit exceeded"),
+            Self::Blocked => write!(f, "Address is blocklisted"),
             Self::IdMissing => write!(f, "ID missing"),
             Self::IdMismatch => write!(f, "ID mismatch"),
+            Self::IdChanged(id) => write!(f, "ID changed to {}", id),
             Self::InitTimeout => write!(f, "Init timed out after {}s", TIMEOUT_INIT.as_secs()),
-            Self::QueryTimeout => write!(f, "Query timed out after {}x RTT", TIMEOUT_FACTOR),
+            Self::QueryTimeout => write!(f, "Query timed out (no response within RTO)"),
             Self::TotalTimeout => write!(f, "Unresponsive for more than {}s", TIMEOUT_TOTAL.as_secs()),
             Self::BencodeInvalid => write!(f, "Received invalid bencode"),
             Self::ProtocolViolation => write!(f, "Protocol violation"),
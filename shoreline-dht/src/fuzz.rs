@@ -0,0 +1,77 @@
+//! Entry points for the fuzz targets in `fuzz/`
+//!
+//! Only available when built with `--cfg fuzzing` (as done by `cargo fuzz`).
+//! Each function feeds arbitrary bytes into a code path parsing untrusted
+//! datagrams and panics on invalid replies.
+
+use crate::common::{Infos, Msg};
+use crate::constants::*;
+use crate::link::LinkTask;
+use crate::util::test::node;
+use crate::{Id, Limits, Peers};
+use bencode_minimal::Value;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::LazyLock;
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+
+/// The [Id] of the remote peer of the link target (messages need it to pass the ID check)
+pub const PEER_ID: &[u8; Id::BYTES] = b"shoreline-fuzz-peer!";
+
+const LOCAL: SocketAddrV6 = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0);
+const REMOTE: SocketAddrV6 = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 9, 0, 0);
+
+static RT: LazyLock<Runtime> =
+    LazyLock::new(|| tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap());
+
+/// Fuzz `node::task::Task::dispatch` (unsolicited messages on the node socket)
+pub fn node_dispatch(data: &[u8]) {
+    RT.block_on(async {
+        let token = CancellationToken::new();
+        let peers = Peers::new(token.clone(), Limits::default());
+        let mut task = node(Id::random(), &peers).unspawned_task(peers);
+        let reply = task.fuzz_dispatch(REMOTE, data).await;
+        if !reply.is_empty() {
+            check_reply(data, &reply);
+        }
+        token.cancel();
+    })
+}
+
+/// Fuzz `link::task::Task::rcvd` (messages routed to a link)
+pub fn link_rcvd(data: &[u8]) {
+    RT.block_on(async {
+        let token = CancellationToken::new();
+        let peers = Peers::new(token.clone(), Limits::default());
+        let node = node(Id::random(), &peers);
+        let peer = peers.get(&Id::from_bytes(PEER_ID)).unwrap();
+        let sock = std::net::UdpSocket::bind(SocketAddr::V6(LOCAL)).unwrap();
+        let SocketAddr::V6(remote) = sock.local_addr().unwrap() else {
+            unreachable!()
+        };
+        let mut task = LinkTask::fuzz(node, peer, remote);
+        let _ = task.fuzz_rcvd(data).await;
+        sock.set_nonblocking(true).unwrap();
+        let mut buf = vec![0; RBUF_SIZE];
+        while let Ok(len) = sock.recv(&mut buf) {
+            check_reply(data, &buf[..len]);
+        }
+        token.cancel();
+    })
+}
+
+/// Fuzz [Infos::decode] (compact `nodes6` lists)
+pub fn infos_decode(data: &[u8]) {
+    if let Some(infos) = Infos::decode(data) {
+        assert_eq!(infos.encode(), data);
+    }
+}
+
+/// Check that `reply` is a valid response or error for the query `query`
+fn check_reply(query: &[u8], reply: &[u8]) {
+    let q = Value::decode(query, BENCODE_MAX_ALLOCS).expect("replied to invalid bencode");
+    let r = Value::decode(reply, BENCODE_MAX_ALLOCS).expect("reply is invalid bencode");
+    assert_eq!(q.get::<&str>(Msg::Y), Some(Msg::Q), "replied to non-query");
+    assert_eq!(r.get::<&[u8]>(Msg::T), q.get::<&[u8]>(Msg::T), "reply has wrong transaction id");
+    assert!(matches!(r.get::<&str>(Msg::Y), Some(Msg::R | Msg::E)), "reply is neither response nor error");
+}
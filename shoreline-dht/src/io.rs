@@ -0,0 +1,320 @@
+//! Batched datagram I/O
+//!
+//! On Linux, a [Batch] is received and sent with a single `recvmmsg`/`sendmmsg`
+//! system call. Elsewhere the datagrams are transferred one by one.
+//!
+//! This is internal API; it is only exported with the `bench` feature.
+
+use crate::constants::*;
+use std::io;
+use std::net::SocketAddrV6;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+/// Socket buffer sizes (`SO_RCVBUF`/`SO_SNDBUF`; the system default if absent)
+/// and whether to keep NAT mappings open
+#[derive(Debug, Clone, Default)]
+pub struct SocketOptions {
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+    /// Ping the peers in the routing table every [KEEPALIVE_INTERVAL] unless the
+    /// node is known to be reachable (see [Reachability](crate::Reachability))
+    ///
+    /// Off by default. Earlier versions always pinged all peers every
+    /// [KEEPALIVE_INTERVAL]; nodes behind a NAT or firewall should switch it on
+    /// to keep their mappings open, or they may become unreachable for peers.
+    pub nat_keepalive: bool,
+}
+
+/// Up to [IO_BATCH_SIZE] datagrams with their remote addresses
+pub struct Batch {
+    bufs: Vec<Vec<u8>>,
+    addrs: Vec<SocketAddrV6>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self { bufs: Vec::with_capacity(IO_BATCH_SIZE), addrs: Vec::with_capacity(IO_BATCH_SIZE) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.addrs.len() >= IO_BATCH_SIZE
+    }
+
+    pub fn clear(&mut self) {
+        self.addrs.clear();
+    }
+
+    /// Add a datagram to be sent to `addr` (returns `false` if the batch is full)
+    pub fn push(&mut self, addr: SocketAddrV6, buf: &[u8]) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let i = self.addrs.len();
+        if self.bufs.len() <= i {
+            self.bufs.push(Vec::with_capacity(RBUF_SIZE));
+        }
+        self.bufs[i].clear();
+        self.bufs[i].extend_from_slice(buf);
+        self.addrs.push(addr);
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SocketAddrV6, &[u8])> {
+        self.addrs.iter().zip(self.bufs.iter()).map(|(a, b)| (*a, b.as_slice()))
+    }
+
+    /// Prepare all buffers for receiving
+    fn reset(&mut self) {
+        self.addrs.clear();
+        self.bufs.resize_with(IO_BATCH_SIZE, Vec::new);
+        self.bufs.iter_mut().for_each(|b| b.resize(RBUF_SIZE, 0));
+    }
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Apply the [SocketOptions] to a socket
+pub(crate) fn configure(sock: &UdpSocket, opts: &SocketOptions) -> io::Result<()> {
+    let sock = socket2::SockRef::from(sock);
+    if let Some(n) = opts.recv_buffer_size {
+        sock.set_recv_buffer_size(n)?;
+    }
+    if let Some(n) = opts.send_buffer_size {
+        sock.set_send_buffer_size(n)?;
+    }
+    Ok(())
+}
+
+/// Receive at least one datagram into `batch` (replacing its contents)
+///
+/// Datagrams from IPv4 addresses are skipped, and so are datagrams truncated
+/// because they exceed [RBUF_SIZE].
+pub async fn recv_batch(sock: &UdpSocket, batch: &mut Batch) -> io::Result<usize> {
+    batch.reset();
+    loop {
+        sock.readable().await?;
+        match sock.try_io(tokio::io::Interest::READABLE, || sys::recv(sock, batch)) {
+            Ok(0) => continue,
+            Ok(n) => return Ok(n),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Send all datagrams in `batch` and clear it
+///
+/// Errors affecting a single datagram (e.g. unreachable destination) drop it
+/// and do not prevent the others from being sent.
+pub async fn send_batch(sock: &UdpSocket, batch: &mut Batch) -> io::Result<usize> {
+    let mut sent = 0;
+    while sent < batch.len() {
+        sock.writable().await?;
+        match sock.try_io(tokio::io::Interest::WRITABLE, || sys::send(sock, batch, sent)) {
+            Ok(n) => sent += n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(_) => sent += 1,
+        }
+    }
+    batch.clear();
+    Ok(sent)
+}
+
+/// Spawn a task sending the datagrams queued on the returned channel in batches
+///
+/// Datagrams queued while a batch is being sent go out with the next one. The
+/// task ends when all senders are dropped. Send errors are ignored.
+pub(crate) fn spawn_sender(sock: Arc<UdpSocket>, capacity: usize) -> mpsc::Sender<(SocketAddrV6, Vec<u8>)> {
+    let (tx, mut rx) = mpsc::channel::<(SocketAddrV6, Vec<u8>)>(capacity);
+    tokio::spawn(async move {
+        let mut batch = Batch::new();
+        let mut queued = Vec::with_capacity(IO_BATCH_SIZE);
+        while rx.recv_many(&mut queued, IO_BATCH_SIZE).await > 0 {
+            for (addr, buf) in queued.drain(..) {
+                batch.push(addr, &buf);
+            }
+            let _ = send_batch(&sock, &mut batch).await;
+        }
+    });
+    tx
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::Batch;
+    use crate::constants::IO_BATCH_SIZE;
+    use std::io;
+    use std::mem::{size_of, zeroed};
+    use std::net::{Ipv6Addr, SocketAddrV6};
+    use std::os::fd::AsRawFd;
+    use tokio::net::UdpSocket;
+
+    const ADDR_LEN: libc::socklen_t = size_of::<libc::sockaddr_in6>() as libc::socklen_t;
+
+    pub fn recv(sock: &UdpSocket, batch: &mut Batch) -> io::Result<usize> {
+        // SAFETY: All-zero is a valid value for these plain C structs
+        let mut names: [libc::sockaddr_in6; IO_BATCH_SIZE] = unsafe { zeroed() };
+        let mut iovs: [libc::iovec; IO_BATCH_SIZE] = unsafe { zeroed() };
+        let mut msgs: [libc::mmsghdr; IO_BATCH_SIZE] = unsafe { zeroed() };
+        for (i, buf) in batch.bufs.iter_mut().enumerate() {
+            iovs[i] = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+            msgs[i].msg_hdr.msg_name = (&mut names[i] as *mut libc::sockaddr_in6).cast();
+            msgs[i].msg_hdr.msg_namelen = ADDR_LEN;
+            msgs[i].msg_hdr.msg_iov = &mut iovs[i];
+            msgs[i].msg_hdr.msg_iovlen = 1;
+        }
+        let fd = sock.as_raw_fd();
+        let flags = libc::MSG_DONTWAIT as _;
+        // SAFETY: All pointers refer to live buffers of the advertised lengths
+        let n = unsafe { libc::recvmmsg(fd, msgs.as_mut_ptr(), IO_BATCH_SIZE as _, flags, std::ptr::null_mut()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut bufs = std::mem::take(&mut batch.bufs);
+        let mut j = 0;
+        for i in 0..n as usize {
+            if names[i].sin6_family as i32 != libc::AF_INET6 || msgs[i].msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                continue;
+            }
+            let ip = Ipv6Addr::from(names[i].sin6_addr.s6_addr);
+            let port = u16::from_be(names[i].sin6_port);
+            let addr = SocketAddrV6::new(ip, port, names[i].sin6_flowinfo, names[i].sin6_scope_id);
+            bufs.swap(i, j);
+            bufs[j].truncate(msgs[i].msg_len as usize);
+            batch.addrs.push(addr);
+            j += 1;
+        }
+        batch.bufs = bufs;
+        Ok(j)
+    }
+
+    pub fn send(sock: &UdpSocket, batch: &Batch, offset: usize) -> io::Result<usize> {
+        // SAFETY: All-zero is a valid value for these plain C structs
+        let mut names: [libc::sockaddr_in6; IO_BATCH_SIZE] = unsafe { zeroed() };
+        let mut iovs: [libc::iovec; IO_BATCH_SIZE] = unsafe { zeroed() };
+        let mut msgs: [libc::mmsghdr; IO_BATCH_SIZE] = unsafe { zeroed() };
+        let mut n = 0;
+        for (i, (addr, buf)) in batch.iter().skip(offset).enumerate() {
+            names[i].sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            names[i].sin6_port = addr.port().to_be();
+            names[i].sin6_addr.s6_addr = addr.ip().octets();
+            names[i].sin6_scope_id = addr.scope_id();
+            iovs[i] = libc::iovec { iov_base: buf.as_ptr() as *mut _, iov_len: buf.len() };
+            msgs[i].msg_hdr.msg_name = (&mut names[i] as *mut libc::sockaddr_in6).cast();
+            msgs[i].msg_hdr.msg_namelen = ADDR_LEN;
+            msgs[i].msg_hdr.msg_iov = &mut iovs[i];
+            msgs[i].msg_hdr.msg_iovlen = 1;
+            n += 1;
+        }
+        let fd = sock.as_raw_fd();
+        let flags = libc::MSG_DONTWAIT as _;
+        // SAFETY: All pointers refer to live buffers of the advertised lengths
+        let n = unsafe { libc::sendmmsg(fd, msgs.as_mut_ptr(), n as _, flags) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::Batch;
+    use std::io;
+    use std::net::SocketAddr;
+    use tokio::net::UdpSocket;
+
+    pub fn recv(sock: &UdpSocket, batch: &mut Batch) -> io::Result<usize> {
+        let (len, addr) = sock.try_recv_from(&mut batch.bufs[0])?;
+        match addr {
+            // A datagram filling the buffer is most likely truncated
+            SocketAddr::V6(_) if len == batch.bufs[0].len() => Ok(0),
+            SocketAddr::V6(addr) => {
+                batch.bufs[0].truncate(len);
+                batch.addrs.push(addr);
+                Ok(1)
+            }
+            SocketAddr::V4(_) => Ok(0),
+        }
+    }
+
+    pub fn send(sock: &UdpSocket, batch: &Batch, offset: usize) -> io::Result<usize> {
+        let (addr, buf) = batch.iter().nth(offset).ok_or(io::ErrorKind::InvalidInput)?;
+        sock.try_send_to(buf, addr.into())?;
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    async fn socket() -> (UdpSocket, SocketAddrV6) {
+        let sock = UdpSocket::bind("[::1]:0").await.unwrap();
+        let SocketAddr::V6(addr) = sock.local_addr().unwrap() else {
+            unreachable!()
+        };
+        (sock, addr)
+    }
+
+    /// Receive batches until `n` datagrams arrived
+    async fn recv(sock: &UdpSocket, n: usize) -> Vec<(SocketAddrV6, Vec<u8>)> {
+        let mut batch = Batch::new();
+        let mut rcvd = vec![];
+        while rcvd.len() < n {
+            recv_batch(sock, &mut batch).await.unwrap();
+            rcvd.extend(batch.iter().map(|(a, b)| (a, b.to_vec())));
+        }
+        rcvd
+    }
+
+    #[tokio::test]
+    async fn round_trip_over_loopback() {
+        let ((tx, from), (rx, to)) = (socket().await, socket().await);
+        let mut batch = Batch::new();
+        let mut n = 0u8;
+        while batch.push(to, &vec![n; n as usize + 1]) {
+            n += 1;
+        }
+        assert_eq!(n as usize, IO_BATCH_SIZE);
+        assert_eq!(send_batch(&tx, &mut batch).await.unwrap(), IO_BATCH_SIZE);
+        assert!(batch.is_empty());
+        let rcvd = recv(&rx, IO_BATCH_SIZE).await;
+        let expected = (0..n).map(|n| (from, vec![n; n as usize + 1])).collect::<Vec<_>>();
+        assert_eq!(rcvd, expected);
+    }
+
+    #[tokio::test]
+    async fn drops_truncated_datagrams() {
+        let ((tx, from), (rx, to)) = (socket().await, socket().await);
+        tx.send_to(&[1; RBUF_SIZE + 1], to).await.unwrap();
+        tx.send_to(&[2; RBUF_SIZE - 1], to).await.unwrap();
+        assert_eq!(recv(&rx, 1).await, vec![(from, vec![2; RBUF_SIZE - 1])]);
+    }
+
+    #[tokio::test]
+    async fn sends_queued_datagrams() {
+        let ((tx, from), (rx, to)) = (socket().await, socket().await);
+        let outbox = spawn_sender(Arc::new(tx), 2 * IO_BATCH_SIZE);
+        for n in 0..2 * IO_BATCH_SIZE as u8 {
+            outbox.try_send((to, vec![n])).unwrap();
+        }
+        let rcvd = recv(&rx, 2 * IO_BATCH_SIZE).await;
+        assert_eq!(rcvd, (0..2 * IO_BATCH_SIZE as u8).map(|n| (from, vec![n])).collect::<Vec<_>>());
+    }
+}
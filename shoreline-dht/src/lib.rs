@@ -1,21 +1,39 @@
+mod blocklist;
+mod bootstrap;
 mod common;
 mod link;
 mod dht;
 mod error;
+mod io;
+mod limits;
 mod net;
 mod node;
 mod nodes;
+mod pcp;
 mod peer;
 mod peers;
+mod seeds;
 mod util;
 mod constants;
+#[cfg(fuzzing)]
+pub mod fuzz;
 
-pub use self::common::{Id, Info, Version};
+pub use self::blocklist::{Blocklist, Blocklists};
+pub use self::bootstrap::BootstrapState;
+pub use self::common::{Id, Info, Infos, ParseIdError, Version};
 pub use self::link::{Link, Status};
 pub use self::dht::DHT;
 pub use self::error::Error;
-pub use self::node::{Node, NodeStat};
+pub use self::io::SocketOptions;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub use self::io::{Batch, recv_batch, send_batch};
+pub use self::limits::{Limits, ResourceUsage};
+pub use self::net::{Policy, Scope};
+pub use self::node::{Cluster, Node, NodeStat, Reachability};
 pub use self::nodes::Nodes;
+pub use self::pcp::{Mapping, PortMapping};
 pub use self::peer::Peer;
 pub use self::peers::Peers;
+pub use self::seeds::{Resolve, SystemResolver, resolve_seeds};
 pub use self::constants::*;
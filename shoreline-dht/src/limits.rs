@@ -0,0 +1,99 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::task::JoinHandle;
+
+/// Upper bounds on the state kept by the DHT
+///
+/// When [Self::max_peers] is reached, the least recently active idle peer is
+/// evicted to make room for a new one. Peers that are still responsive are
+/// never evicted; the new peer is rejected instead.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Maximum number of [Peer](crate::Peer)s
+    pub max_peers: usize,
+    /// Maximum number of [Link](crate::Link)s across all peers
+    pub max_links: usize,
+    /// Maximum number of [Link](crate::Link)s per peer (one per address pair)
+    pub max_links_per_peer: usize,
+    /// Maximum number of tasks for peers and links (see [ResourceUsage::tasks])
+    ///
+    /// No peers or links are created while reached and queries from peers are
+    /// answered with error 202.
+    pub max_tasks: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { max_peers: 2048, max_links: 4096, max_links_per_peer: 4, max_tasks: 16384 }
+    }
+}
+
+/// Resources currently used by the DHT (see [DHT::resource_usage](crate::DHT::resource_usage))
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceUsage {
+    /// Alive tasks spawned for peers and links (see [Limits::max_tasks])
+    ///
+    /// Each peer has one task, each link has two and one per query it answers.
+    /// The few tasks per node are not included.
+    pub tasks: usize,
+    pub peers: usize,
+    pub links: usize,
+    /// Sockets held by the nodes (see [Node::sockets](crate::Node::sockets))
+    pub sockets: usize,
+}
+
+impl Display for ResourceUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} tasks, {} peers, {} links, {} sockets", self.tasks, self.peers, self.links, self.sockets)
+    }
+}
+
+/// Counter of the alive tasks spawned for peers and links (see [Limits::max_tasks])
+#[derive(Debug, Clone)]
+pub(crate) struct Tasks {
+    count: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl Tasks {
+    pub fn new(max: usize) -> Self {
+        Self { count: Arc::default(), max }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Check whether the maximum number of tasks is reached
+    pub fn is_full(&self) -> bool {
+        self.count() >= self.max
+    }
+
+    /// Count `fut` as a task until it completes or is dropped
+    pub fn track<F: Future>(&self, fut: F) -> impl Future<Output = F::Output> + use<F> {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let guard = TaskGuard(self.count.clone());
+        async move {
+            let _guard = guard;
+            fut.await
+        }
+    }
+
+    /// Spawn `fut` as a counted task (see [Self::track])
+    pub fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        tokio::spawn(self.track(fut))
+    }
+}
+
+struct TaskGuard(Arc<AtomicUsize>);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
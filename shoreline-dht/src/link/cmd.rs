@@ -5,6 +5,7 @@ use tokio::sync::oneshot;
 #[derive(Debug)]
 pub enum Command {
     Ping(CmdPing),
+    Probe(CmdProbe),
     FindNode(CmdFindNode),
 }
 
@@ -14,6 +15,9 @@ impl Command {
             Command::Ping(t) => {
                 let _ = t.response.send(Err(e));
             }
+            Command::Probe(t) => {
+                let _ = t.response.send(Err(e));
+            }
             Command::FindNode(t) => {
                 let _ = t.response.send(Err(e));
             }
@@ -33,6 +37,18 @@ impl CmdPing {
     }
 }
 
+#[derive(Debug)]
+pub struct CmdProbe {
+    pub response: oneshot::Sender<Result<(), Error>>,
+}
+
+impl CmdProbe {
+    pub fn new() -> (Self, oneshot::Receiver<Result<(), Error>>) {
+        let (tx, rx) = oneshot::channel();
+        (Self { response: tx }, rx)
+    }
+}
+
 #[derive(Debug)]
 pub struct CmdFindNode {
     pub target: Id,
@@ -52,6 +68,12 @@ impl Into<Command> for CmdPing {
     }
 }
 
+impl From<CmdProbe> for Command {
+    fn from(cmd: CmdProbe) -> Self {
+        Command::Probe(cmd)
+    }
+}
+
 impl Into<Command> for CmdFindNode {
     fn into(self) -> Command {
         Command::FindNode(self)
@@ -4,13 +4,16 @@ mod status;
 mod task;
 mod trxs;
 
+pub(crate) use self::stat::Stat;
 pub use self::status::Status;
+#[cfg(fuzzing)]
+pub(crate) use self::task::Task as LinkTask;
 
 use crate::common::Id;
+use crate::constants::*;
 use crate::common::Infos;
 use crate::error::Error;
-use crate::link::cmd::{CmdFindNode, CmdPing, Command};
-use crate::link::stat::Stat;
+use crate::link::cmd::{CmdFindNode, CmdPing, CmdProbe, Command};
 use crate::link::task::Task;
 use crate::{Node, Peer};
 use std::net::SocketAddrV6;
@@ -24,17 +27,19 @@ pub struct Link {
     node: Arc<Node>,
     peer: Arc<Peer>,
     addr: SocketAddrV6,
-    cmds: mpsc::UnboundedSender<Command>,
+    cmds: mpsc::Sender<Command>,
     stat: watch::Receiver<Stat>,
+    keepalive: watch::Sender<bool>,
     token: CancellationToken,
 }
 
 impl Link {
     pub fn new(node: Arc<Node>, peer: Arc<Peer>, addr: SocketAddrV6) -> Arc<Self> {
-        let (cmds, cmds_) = mpsc::unbounded_channel();
+        let (cmds, cmds_) = mpsc::channel(LINK_CMDS_MAX);
         let (stat_, stat) = watch::channel(Stat::new());
-        let token = Task::spawn(node.clone(), peer.clone(), addr, cmds_, stat_);
-        Arc::new(Self { node, peer, addr, token, cmds, stat })
+        let (keepalive, keepalive_) = watch::channel(false);
+        let token = Task::spawn(node.clone(), peer.clone(), addr, cmds_, stat_, keepalive_);
+        Arc::new(Self { node, peer, addr, token, cmds, stat, keepalive })
     }
 
     pub fn node(&self) -> &Arc<Node> {
@@ -57,6 +62,11 @@ impl Link {
         &self.token
     }
 
+    /// Ping the peer every [KEEPALIVE_INTERVAL] if nothing else was sent (NAT keepalive)
+    pub fn set_keepalive(&self, on: bool) {
+        self.keepalive.send_if_modified(|k| std::mem::replace(k, on) != on);
+    }
+
     pub async fn init(&self) -> Result<Status, Error> {
         let mut stat = self.stat.clone();
         while matches!(stat.borrow().status, Status::Init) {
@@ -67,13 +77,59 @@ impl Link {
 
     pub async fn ping(&self) -> Result<(), Error> {
         let (trx, rx) = CmdPing::new();
-        self.cmds.send(trx.into()).map_err(|_| Error::LinkTerminated)?;
+        self.command(trx.into())?;
         Ok(rx.await.map_err(|_| Error::LinkTerminated)??)
     }
 
+    /// Ask the peer to query our node from another port (see [Reachability](crate::Reachability))
+    ///
+    /// Only shoreline peers understand this query.
+    pub async fn probe(&self) -> Result<(), Error> {
+        let (trx, rx) = CmdProbe::new();
+        self.command(trx.into())?;
+        rx.await.map_err(|_| Error::LinkTerminated)?
+    }
+
     pub async fn find_node(&self, id: &Id) -> Result<Infos, Error> {
         let (trx, rx) = CmdFindNode::new(id.clone());
-        self.cmds.send(trx.into()).map_err(|_| Error::LinkTerminated)?;
+        self.command(trx.into())?;
         Ok(rx.await.map_err(|_| Error::LinkTerminated)??)
     }
+
+    /// Queue a command for the link task without waiting
+    ///
+    /// Fails with [Error::Overloaded] if [LINK_CMDS_MAX] commands are already pending.
+    fn command(&self, cmd: Command) -> Result<(), Error> {
+        self.cmds.try_send(cmd).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => Error::Overloaded,
+            mpsc::error::TrySendError::Closed(_) => Error::LinkTerminated,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test::node;
+    use crate::{Limits, Peers};
+
+    #[tokio::test]
+    async fn fails_when_commands_are_full() {
+        let token = CancellationToken::new();
+        let peers = Peers::new(token.clone(), Limits::default());
+        let node = node(Id::random(), &peers);
+        let peer = peers.get(&Id::random()).unwrap();
+        let (cmds, receiver) = mpsc::channel(LINK_CMDS_MAX);
+        let stat = watch::channel(Stat::new()).1;
+        let keepalive = watch::channel(false).0;
+        let addr = "[::1]:9".parse().unwrap();
+        let link = Link { node, peer, addr, cmds, stat, keepalive, token: token.clone() };
+        let pending = (0..LINK_CMDS_MAX).map(|_| link.command(CmdPing::new().0.into())).collect::<Vec<_>>();
+        assert!(pending.iter().all(Result::is_ok));
+        assert!(matches!(link.find_node(&Id::random()).await, Err(Error::Overloaded)));
+        assert!(matches!(link.ping().await, Err(Error::Overloaded)));
+        drop(receiver);
+        assert!(matches!(link.ping().await, Err(Error::LinkTerminated)));
+        token.cancel();
+    }
 }
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use tokio::time::Duration;
 use super::super::{Error, Version};
 use tokio::time::Instant;
+use crate::constants::*;
 use crate::link::Status;
 
 #[derive(Debug, Clone)]
@@ -13,7 +14,18 @@ pub struct Stat {
     pub rx_packets: u64,
     pub rx_last: Instant,
     pub status: Status,
-    pub rtt: Option<Duration>,
+    /// Smoothed round-trip time (SRTT, RFC 6298)
+    pub srtt: Option<Duration>,
+    /// Round-trip time variation (RTTVAR, RFC 6298), i.e. the jitter
+    pub rttvar: Duration,
+    pub rtt_min: Option<Duration>,
+    pub rtt_max: Option<Duration>,
+    pub queries: u64,
+    pub timeouts: u64,
+    /// Number of consecutive queries that timed out
+    pub failures: u32,
+    /// Number of queries answered with error 202 because too many were pending
+    pub shed: u64,
     pub version: Option<Version>,
     pub error: Option<Arc<Error>>
 }
@@ -27,7 +39,14 @@ impl Stat {
             rx_packets: 0,
             rx_last: tokio::time::Instant::now(),
             status: Status::Init,
-            rtt: None,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rtt_min: None,
+            rtt_max: None,
+            queries: 0,
+            timeouts: 0,
+            failures: 0,
+            shed: 0,
             version: None,
             error: None,
         }
@@ -42,4 +61,68 @@ impl Stat {
         self.rx_bytes = self.rx_bytes.saturating_add(n);
         self.rx_packets = self.rx_packets.saturating_add(1);
     }
+
+    /// Get the fraction of queries that timed out
+    pub fn loss(&self) -> f32 {
+        if self.queries == 0 { 0.0 } else { self.timeouts as f32 / self.queries as f32 }
+    }
+
+    /// Update SRTT, RTTVAR, minimum and maximum with a round-trip time sample (RFC 6298)
+    pub fn add_rtt(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        self.rtt_min = Some(self.rtt_min.map_or(rtt, |x| x.min(rtt)));
+        self.rtt_max = Some(self.rtt_max.map_or(rtt, |x| x.max(rtt)));
+    }
+
+    /// Get the retransmission timeout (RTO, RFC 6298)
+    ///
+    /// The RTO is SRTT + 4 * RTTVAR within [RTO_MIN] and [RTO_MAX], doubled for
+    /// each consecutive failure. Without any sample it is [TIMEOUT_INIT].
+    pub fn rto(&self) -> Duration {
+        let Some(srtt) = self.srtt else {
+            return TIMEOUT_INIT;
+        };
+        let rto = (srtt + self.rttvar * 4).clamp(RTO_MIN, RTO_MAX);
+        rto.saturating_mul(1 << self.failures.min(16)).min(RTO_MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn tracks_srtt_and_rttvar() {
+        let mut stat = Stat::new();
+        stat.add_rtt(ms(100));
+        assert_eq!((stat.srtt, stat.rttvar), (Some(ms(100)), ms(50)));
+        stat.add_rtt(ms(300));
+        assert_eq!((stat.srtt, stat.rttvar), (Some(ms(125)), ms(87) + Duration::from_micros(500)));
+        assert_eq!((stat.rtt_min, stat.rtt_max), (Some(ms(100)), Some(ms(300))));
+    }
+
+    #[test]
+    fn bounds_and_backs_off_rto() {
+        let mut stat = Stat::new();
+        assert_eq!(stat.rto(), TIMEOUT_INIT);
+        stat.add_rtt(ms(10));
+        assert_eq!(stat.rto(), RTO_MIN);
+        stat.add_rtt(ms(2000));
+        assert!(stat.rto() > ms(2000) && stat.rto() < RTO_MAX);
+        stat.failures = 3;
+        assert_eq!(stat.rto(), RTO_MAX);
+    }
 }
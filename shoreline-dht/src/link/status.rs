@@ -2,8 +2,13 @@ use std::fmt::Display;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Status {
+    /// Not responded yet
     Init,
+    /// Responded recently (BEP 5)
     Good,
+    /// Inactive for [LINK_QUESTIONABLE_AFTER](crate::LINK_QUESTIONABLE_AFTER) or a query timed out
+    Questionable,
+    /// [LINK_FAILURES_MAX](crate::LINK_FAILURES_MAX) queries in a row timed out (bad in BEP 5)
     Fail,
     Term,
 }
@@ -16,6 +21,17 @@ impl Status {
     pub fn is_expendable(&self) -> bool {
         matches!(self, Status::Fail | Status::Term)
     }
+
+    /// Rank by preference for sending queries (lower is better)
+    pub fn rank(&self) -> u8 {
+        match self {
+            Status::Good => 0,
+            Status::Questionable => 1,
+            Status::Init => 2,
+            Status::Fail => 3,
+            Status::Term => 4,
+        }
+    }
 }
 
 impl Default for Status {
@@ -29,6 +45,7 @@ impl Display for Status {
         match self {
             Status::Init => write!(f, "INIT"),
             Status::Good => write!(f, "GOOD"),
+            Status::Questionable => write!(f, "QUES"),
             Status::Fail => write!(f, "FAIL"),
             Status::Term => write!(f, "TERM"),
         }
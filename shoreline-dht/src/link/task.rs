@@ -2,21 +2,21 @@ use super::super::Error;
 use super::super::common::Infos;
 use super::super::common::*;
 use super::super::{Id, Version};
-use super::cmd::{CmdFindNode, CmdPing, Command};
+use super::cmd::{CmdFindNode, CmdPing, CmdProbe, Command};
 use super::status::Status;
 use super::trxs::Trxs;
 use crate::constants::*;
 use crate::link::stat::Stat;
-use crate::util::{check, socket, socket_connected};
+use crate::node::Route;
+use crate::util::check;
 use crate::{Node, Peer};
 use bencode_minimal::Value;
 use std::net::SocketAddrV6;
 use std::sync::Arc;
-use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 use tokio::sync::watch;
 use tokio::task::JoinSet;
-use tokio::time::{Instant, Interval, interval_at};
+use tokio::time::{Instant, sleep_until};
 use tokio_util::sync::CancellationToken;
 
 const EPROTO: Error = Error::ProtocolViolation;
@@ -26,11 +26,17 @@ pub struct Task {
     node: Arc<Node>,
     peer: Arc<Peer>,
     addr: SocketAddrV6,
-    sock: UdpSocket,
-    ping: Interval,
+    route: Route,
+    created: Instant,
+    /// Time the last datagram was sent
+    sent: Instant,
+    /// Time the last query was sent
+    queried: Instant,
+    keepalive: watch::Receiver<bool>,
     trxs: Trxs,
-    qrys: JoinSet<Result<Vec<u8>, Error>>,
-    cmds: mpsc::UnboundedReceiver<Command>,
+    /// Pending query handlers yielding the response (or the error if the node shed the query)
+    qrys: JoinSet<Result<Vec<u8>, Vec<u8>>>,
+    cmds: mpsc::Receiver<Command>,
     stat: watch::Sender<Stat>,
     token: CancellationToken,
 }
@@ -40,31 +46,44 @@ impl Task {
         node: Arc<Node>,
         peer: Arc<Peer>,
         addr: SocketAddrV6,
-        cmds: mpsc::UnboundedReceiver<Command>,
+        cmds: mpsc::Receiver<Command>,
         stat: watch::Sender<Stat>,
+        keepalive: watch::Receiver<bool>,
     ) -> CancellationToken {
+        let this = Self::new(node, peer, addr, cmds, stat, keepalive);
+        let ctok = this.token.clone();
+        this.peer.tasks().clone().spawn(Box::new(this).run());
+        ctok
+    }
+
+    pub fn new(
+        node: Arc<Node>,
+        peer: Arc<Peer>,
+        addr: SocketAddrV6,
+        cmds: mpsc::Receiver<Command>,
+        stat: watch::Sender<Stat>,
+        keepalive: watch::Receiver<bool>,
+    ) -> Self {
         let ctok = peer.token().child_token();
-        let this = Self {
+        let now = Instant::now();
+        Self {
+            route: node.register(&addr, ctok.clone()),
             node,
             peer,
             addr,
-            sock: socket(),
-            ping: interval_at(Instant::now() + PING_STARTUP_DELAY, PING_INTERVAL),
+            created: now,
+            sent: now,
+            queried: now,
+            keepalive,
             trxs: Trxs::new(&stat),
             qrys: JoinSet::new(),
             cmds,
             stat,
-            token: ctok.clone(),
-        };
-        tokio::task::spawn(Box::new(this).run());
-        ctok
+            token: ctok,
+        }
     }
 
-    /// Run connect on the UDP socket pair and eventually call [Self::run_connected]
-    ///
-    /// This function will keep trying to connect with exponential backoff until
-    /// successful. While not connected, it will reject incoming commands with
-    /// [Error::PeerNotConnected].
+    /// Run the link until it fails or is cancelled and mark it as terminated
     async fn run(mut self: Box<Self>) {
         if let Err(e) = self.run_().await {
             self.set_fail(e);
@@ -73,28 +92,26 @@ impl Task {
         self.token.cancel();
     }
 
-    /// Run the communication on the connected UDP socket
+    /// Run the communication with the peer
     ///
+    /// Datagrams from the peer are routed here by the node's socket.
     /// This function handles incoming and outgoing messages, timeouts,
-    /// and periodic pings. It will only return with error.
+    /// and pings (see [Self::next_ping]). It will only return with error.
     async fn run_(&mut self) -> Result<(), Error> {
-        let bind = self.node.addr();
-        let conn = self.addr;
-        self.sock = socket_connected(bind, &conn).map_err(Error::Socket)?;
-
-        let mut rbuf = vec![0u8; RBUF_SIZE];
-
         loop {
+            let ping = self.next_ping();
             tokio::select! {
-                // Periodic ping if nothing else has been sent recently
-                _ = self.ping.tick() => {
+                // Ping to confirm the peer's status or to keep the NAT mapping open
+                _ = sleep_until(ping.unwrap_or(self.created)), if ping.is_some() => {
+                    self.set_questionable();
                     let cmd = CmdPing::new().0;
                     self.exec_ping(cmd).await?;
                 }
+                // Keepalive switched on or off
+                Ok(()) = self.keepalive.changed() => (),
                 // Incoming message
-                res = self.sock.recv(&mut rbuf) => {
-                    let len = res.map_err(Error::Socket)?;
-                    self.rcvd(&rbuf[..len]).await?;
+                Some(buf) = self.route.recv() => {
+                    self.rcvd(&buf).await?;
                 }
                 // Incoming command
                 Some(cmd) = self.cmds.recv() => {
@@ -106,7 +123,10 @@ impl Task {
                 }
                 // Completed query
                 Some(res) = self.qrys.join_next() => {
-                    let msg = res.unwrap()?;
+                    let msg = res.unwrap().unwrap_or_else(|e| {
+                        self.stat.send_modify(|s| s.shed += 1);
+                        e
+                    });
                     self.send(&msg).await?;
                 }
                 _ = self.token.cancelled() => {
@@ -120,14 +140,25 @@ impl Task {
     }
 
     fn timeout(&mut self, cmd: Command) -> Result<(), Error> {
+        self.stat.send_modify(|s| {
+            s.timeouts += 1;
+            s.failures += 1;
+        });
         let stat = self.stat.borrow();
-        let status = stat.status;
+        let (status, failures) = (stat.status, stat.failures);
         let elapsed = stat.rx_last.elapsed();
         drop(stat);
         if status == Status::Init {
             cmd.reject(Error::InitTimeout);
             self.set_fail(Error::InitTimeout);
             Err(Error::InitTimeout)
+        } else if failures < LINK_FAILURES_MAX {
+            cmd.reject(Error::QueryTimeout);
+            self.stat.send_modify(|s| {
+                s.status = Status::Questionable;
+                s.error = Some(Arc::new(Error::QueryTimeout));
+            });
+            Ok(())
         } else if elapsed > TIMEOUT_TOTAL {
             cmd.reject(Error::TotalTimeout);
             self.set_fail(Error::TotalTimeout);
@@ -139,6 +170,28 @@ impl Task {
         }
     }
 
+    /// Get the time of the next ping (if any) according to the peer's [Status] (BEP 5)
+    ///
+    /// No ping is sent while a query is pending. A new link is pinged unless it
+    /// already sent a query, a good one once it turns questionable after
+    /// [LINK_QUESTIONABLE_AFTER] without activity and any other one every
+    /// [LINK_RETRY_INTERVAL]. With NAT keepalive (see [Link::set_keepalive](crate::Link::set_keepalive)),
+    /// the peer is also pinged if nothing was sent for [KEEPALIVE_INTERVAL].
+    fn next_ping(&self) -> Option<Instant> {
+        if !self.trxs.is_empty() {
+            return None;
+        }
+        let stat = self.stat.borrow();
+        let next = match stat.status {
+            Status::Init if stat.queries == 0 => self.created + PING_STARTUP_DELAY,
+            Status::Good => stat.rx_last + LINK_QUESTIONABLE_AFTER,
+            Status::Init | Status::Questionable | Status::Fail => self.queried + LINK_RETRY_INTERVAL,
+            Status::Term => return None,
+        };
+        let keepalive = self.keepalive.borrow().then(|| self.sent + KEEPALIVE_INTERVAL);
+        Some(keepalive.map_or(next, |k| k.min(next)))
+    }
+
     /// Handle received message (either query, response or error)
     async fn rcvd(&mut self, buf: &[u8]) -> Result<(), Error> {
         self.stat.send_modify(|s| s.add_rx_bytes(buf.len() as u64));
@@ -155,40 +208,81 @@ impl Task {
 
     /// Handle received query message
     ///
-    /// Checks the peer ID and throws an error on mismatch.
+    /// Checks the peer ID and throws an error on mismatch (an unsolicited query
+    /// is no evidence of an ID change, see [Self::id_changed]). Lookups are
+    /// answered with error 202 if
+    /// [LINK_QUERIES_MAX] of them are already pending or the task limit is
+    /// reached (see [Limits::max_tasks](crate::Limits::max_tasks)).
     async fn rcvd_query(&mut self, msg: &Value<'_>) -> Result<(), Error> {
         let t = msg.get::<&[u8]>(Msg::T).ok_or(EPROTO)?;
         let q = msg.get::<&str>(Msg::Q).ok_or(EPROTO)?;
         let a = msg.get::<&Value<'_>>(Msg::A).ok_or(EPROTO)?;
         let pid = a.get::<Id>(Msg::ID).ok_or(Error::IdMissing)?;
         check(&pid == self.peer.id()).ok_or(Error::IdMismatch)?;
+        self.set_active();
         match q {
             Msg::PING => self.rcvd_query_ping(t).await,
+            Msg::PROBE => self.rcvd_query_probe(t).await,
+            Msg::FIND_NODE | Msg::GET_PEERS if self.is_overloaded() => self.shed(t).await,
             Msg::FIND_NODE => self.rcvd_query_find_node(msg, t).await,
             Msg::GET_PEERS => self.rcvd_query_get_peers(msg, t).await,
             Msg::ANNOUNCE_PEER => self.rcvd_query_announce_peer(t).await,
-            _ => self.send(&Msg::error_204(t).encode()).await,
+            _ => self.send(&Msg::error_204(t, &CompactAddr::encode(&self.addr)).encode()).await,
         }
     }
 
+    /// Check whether lookups must be shed (see [Self::rcvd_query])
+    fn is_overloaded(&self) -> bool {
+        self.qrys.len() >= LINK_QUERIES_MAX || self.peer.tasks().is_full()
+    }
+
+    /// Answer a query with error 202 instead of handling it (load shedding)
+    async fn shed(&mut self, t: &[u8]) -> Result<(), Error> {
+        self.stat.send_modify(|s| s.shed += 1);
+        self.send(&Msg::error_202(t, &CompactAddr::encode(&self.addr)).encode()).await
+    }
+
     /// Handle received ping query
     async fn rcvd_query_ping(&mut self, t: &[u8]) -> Result<(), Error> {
-        let r = Msg::ping_response(t, self.node.id()).encode();
+        let r = Msg::ping_response(t, &CompactAddr::encode(&self.addr), self.node.id()).encode();
         self.send(&r).await
     }
 
+    /// Handle received probe query
+    ///
+    /// Responds and sends a ping to the peer from the node's probe socket (see
+    /// [Node::probe_socket]), which only arrives if the peer can be reached
+    /// unsolicited. Probes are ignored until the peer has responded to us, as
+    /// they could otherwise be used for reflection towards spoofed addresses.
+    async fn rcvd_query_probe(&mut self, t: &[u8]) -> Result<(), Error> {
+        if self.stat.borrow().status == Status::Init {
+            return Ok(());
+        }
+        let r = Msg::probe_response(t, &CompactAddr::encode(&self.addr), self.node.id()).encode();
+        self.send(&r).await?;
+        let tid = rand::random::<u64>().to_be_bytes();
+        let q = Msg::ping_query(&tid, self.node.id()).encode();
+        let _ = self.node.probe_socket().send_to(&q, self.addr).await;
+        Ok(())
+    }
+
     /// Handle received find_node query
     async fn rcvd_query_find_node(&mut self, msg: &Value<'_>, t: &[u8]) -> Result<(), Error> {
         let t = t.to_vec();
         let a = msg.get::<&Value<'_>>(Msg::A).ok_or(EPROTO)?;
         let target = a.get::<Id>(Msg::TARGET).ok_or(EPROTO)?;
+        let from = Info::new(*self.peer.id(), self.addr);
+        let ip = CompactAddr::encode(&self.addr);
         let node = self.node.clone();
-        self.qrys.spawn(async move {
-            let n6 = node.find(&target).await.unwrap_or_default();
+        let reply = async move {
+            let Ok(n6) = node.find(&target, &from).await else {
+                return Err(Msg::error_202(&t, &ip).encode());
+            };
             let n6 = Infos::from(n6).encode();
-            let m = Msg::find_node_response(&t, node.id(), &n6);
+            let m = Msg::find_node_response(&t, &ip, node.id(), &n6);
             Ok(m.encode())
-        });
+        };
+        self.qrys.spawn(self.peer.tasks().track(reply));
         Ok(())
     }
 
@@ -197,19 +291,24 @@ impl Task {
         let t = t.to_vec();
         let a = msg.get::<&Value<'_>>(Msg::A).ok_or(EPROTO)?;
         let info_hash = a.get::<Id>(Msg::INFO_HASH).ok_or(EPROTO)?;
+        let from = Info::new(*self.peer.id(), self.addr);
+        let ip = CompactAddr::encode(&self.addr);
         let node = self.node.clone();
-        self.qrys.spawn(async move {
-            let n6 = node.find(&info_hash).await.unwrap_or_default();
+        let reply = async move {
+            let Ok(n6) = node.find(&info_hash, &from).await else {
+                return Err(Msg::error_202(&t, &ip).encode());
+            };
             let n6 = Infos::from(n6).encode();
-            let m = Msg::get_peers_response(&t, node.id(), Msg::TOKEN_VALUE.as_bytes(), &n6);
+            let m = Msg::get_peers_response(&t, &ip, node.id(), Msg::TOKEN_VALUE.as_bytes(), &n6);
             Ok(m.encode())
-        });
+        };
+        self.qrys.spawn(self.peer.tasks().track(reply));
         Ok(())
     }
 
     /// Handle received announce_peer query
     async fn rcvd_query_announce_peer(&mut self, t: &[u8]) -> Result<(), Error> {
-        let msg = Msg::announce_peer_response(&t, self.node.id()).encode();
+        let msg = Msg::announce_peer_response(&t, &CompactAddr::encode(&self.addr), self.node.id()).encode();
         self.send(&msg).await
     }
 
@@ -220,15 +319,30 @@ impl Task {
     /// and calls the appropriate handler. The message is ignored if no
     /// matching transaction is found as this might happen on restart and is
     /// not necessarily an error. On successful handling, the peer is marked as good
-    /// and any error is cleared and the exponential backoff reset.
+    /// and any error is cleared and the exponential backoff reset. The address
+    /// the peer reports for us (if any) counts as a vote on our external address.
+    /// A response to one of our queries with a different ID means that the
+    /// peer changed its ID (see [Self::id_changed]) and fails the query with
+    /// [Error::IdChanged]; otherwise it is rejected.
     async fn rcvd_response(&mut self, msg: &Value<'_>) -> Result<(), Error> {
         let t = msg.get(Msg::T).map(u64::from_be_bytes).ok_or(EPROTO)?;
         let r = msg.get::<&Value<'_>>(Msg::R).ok_or(EPROTO)?;
         let pid = r.get::<Id>(Msg::ID).ok_or(Error::IdMissing)?;
-        check(self.peer.id() == &pid).ok_or(Error::IdMismatch)?;
+        if self.peer.id() != &pid {
+            let cmd = self.trxs.resolve(t).ok_or(Error::IdMismatch)?;
+            cmd.reject(Error::IdChanged(pid));
+            self.id_changed(pid);
+            return Ok(());
+        }
         if let Some(cmd) = self.trxs.resolve(t) {
+            if let Some(ip) = msg.get::<&[u8]>(Msg::IP).and_then(CompactAddr::decode) {
+                self.node.vote(&self.addr, &ip);
+            }
             match cmd {
                 Command::Ping(cmd) => self.rcvd_response_ping(cmd).await?,
+                Command::Probe(cmd) => {
+                    let _ = cmd.response.send(Ok(()));
+                }
                 Command::FindNode(cmd) => self.rcvd_response_find_node(cmd, r).await?,
             }
             self.set_good();
@@ -236,6 +350,14 @@ impl Task {
         Ok(())
     }
 
+    /// Hand the address over to `id` (e.g. after the peer restarted)
+    ///
+    /// The node establishes a link to the new ID, which displaces this one, and
+    /// replaces the table entry once it is established (see [Node::migrate]).
+    fn id_changed(&self, id: Id) {
+        let _ = self.node.migrate(self.peer.id(), &Info::new(id, self.addr));
+    }
+
     /// Handle received ping response
     async fn rcvd_response_ping(&mut self, cmd: CmdPing) -> Result<(), Error> {
         let _ = cmd.response.send(Ok(()));
@@ -260,6 +382,9 @@ impl Task {
                 Command::Ping(x) => {
                     let _ = x.response.send(Err(e));
                 }
+                Command::Probe(x) => {
+                    let _ = x.response.send(Err(e));
+                }
                 Command::FindNode(x) => {
                     let _ = x.response.send(Err(e));
                 }
@@ -272,36 +397,49 @@ impl Task {
     async fn exec(&mut self, cmd: Command) -> Result<(), Error> {
         match cmd {
             Command::Ping(cmd) => self.exec_ping(cmd).await,
+            Command::Probe(cmd) => self.exec_probe(cmd).await,
             Command::FindNode(cmd) => self.exec_find_node(cmd).await,
         }
     }
 
     /// Execute outgoing ping command
     async fn exec_ping(&mut self, cmd: CmdPing) -> Result<(), Error> {
+        self.queried = Instant::now();
         let tid = self.trxs.start(cmd).to_be_bytes();
         let msg = Msg::ping_query(&tid, self.node.id());
         let buf = msg.encode();
         self.send(&buf).await
     }
 
+    /// Execute outgoing probe command
+    async fn exec_probe(&mut self, cmd: CmdProbe) -> Result<(), Error> {
+        self.queried = Instant::now();
+        let tid = self.trxs.start(cmd).to_be_bytes();
+        let msg = Msg::probe_query(&tid, self.node.id());
+        let buf = msg.encode();
+        self.send(&buf).await
+    }
+
     /// Execute outgoing find_node command
     async fn exec_find_node(&mut self, cmd: CmdFindNode) -> Result<(), Error> {
         let tgt = cmd.target;
+        self.queried = Instant::now();
         let tid = self.trxs.start(cmd).to_be_bytes();
         let msg = Msg::find_node_query(&tid, self.node.id(), &tgt);
         let buf = msg.encode();
         self.send(&buf).await
     }
 
-    /// Send message on the UDP socket
+    /// Send message on the node's UDP socket (see [Node::send])
     ///
-    /// The ping timer is reset and the bytes sent are accounted for after sending.
+    /// The ping timer is reset and the bytes sent are accounted for once queued.
     async fn send(&mut self, buf: &[u8]) -> Result<(), Error> {
-        self.ping.reset();
-        self.sock.send(buf).await.map_err(Error::Socket)?;
-        self.stat.send_modify(|s| {
-            s.add_tx_bytes(buf.len() as u64);
-        });
+        self.sent = Instant::now();
+        if self.node.send(&self.addr, buf) {
+            self.stat.send_modify(|s| {
+                s.add_tx_bytes(buf.len() as u64);
+            });
+        }
         Ok(())
     }
 
@@ -321,10 +459,34 @@ impl Task {
         self.stat.send_modify(|s| {
             s.status = Status::Good;
             s.rx_last = Instant::now();
+            s.failures = 0;
             s.error = None;
         });
     }
 
+    /// Count a query as activity if the peer has responded before (BEP 5)
+    fn set_active(&self) {
+        self.stat.send_if_modified(|s| {
+            if !matches!(s.status, Status::Good | Status::Questionable) || s.failures > 0 {
+                return false;
+            }
+            s.status = Status::Good;
+            s.rx_last = Instant::now();
+            true
+        });
+    }
+
+    /// Set a good peer to [Status::Questionable] if it has been inactive for [LINK_QUESTIONABLE_AFTER]
+    fn set_questionable(&self) {
+        self.stat.send_if_modified(|s| {
+            let inactive = s.status == Status::Good && s.rx_last.elapsed() >= LINK_QUESTIONABLE_AFTER;
+            if inactive {
+                s.status = Status::Questionable;
+            }
+            inactive
+        });
+    }
+
     /// Set the peer status to [Status::Fail] status and sets the error
     fn set_fail<E: Into<Error>>(&self, e: E) {
         self.stat.send_modify(|s| {
@@ -339,3 +501,144 @@ impl Task {
         });
     }
 }
+
+#[cfg(fuzzing)]
+impl Task {
+    /// Create an unspawned task to be driven by the fuzzer
+    ///
+    /// Replies are sent to `addr`, so the fuzzer may bind a socket there to inspect them.
+    pub fn fuzz(node: Arc<Node>, peer: Arc<Peer>, addr: SocketAddrV6) -> Self {
+        let cmds = mpsc::channel(LINK_CMDS_MAX).1;
+        let stat = watch::channel(Stat::new()).0;
+        Self::new(node, peer, addr, cmds, stat, watch::channel(false).1)
+    }
+
+    /// Feed a received datagram into [Self::rcvd] and send the replies of all spawned queries
+    pub async fn fuzz_rcvd(&mut self, buf: &[u8]) -> Result<(), Error> {
+        let res = self.rcvd(buf).await;
+        while let Some(r) = self.qrys.join_next().await {
+            let (Ok(msg) | Err(msg)) = r.unwrap();
+            self.send(&msg).await?;
+        }
+        self.node.fuzz_flush().await;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test::node;
+    use crate::{Limits, Peers};
+    use std::net::SocketAddr;
+    use tokio::net::UdpSocket;
+    use tokio::time::{Duration, timeout};
+
+    /// Create an unspawned task for a link to an unused address (with its keepalive switch)
+    fn task(token: &CancellationToken) -> (Task, watch::Sender<bool>) {
+        let peers = Peers::new(token.clone(), Limits::default());
+        let node = node(Id::random(), &peers);
+        let peer = peers.get(&Id::random()).unwrap();
+        let cmds = mpsc::channel(LINK_CMDS_MAX).1;
+        let stat = watch::channel(Stat::new()).0;
+        let (keepalive, keepalive_) = watch::channel(false);
+        (Task::new(node, peer, "[::1]:1".parse().unwrap(), cmds, stat, keepalive_), keepalive)
+    }
+
+    #[tokio::test]
+    async fn rejects_queries_with_other_id() {
+        let token = CancellationToken::new();
+        let (mut task, _) = task(&token);
+        let res = task.rcvd(&Msg::ping_query(b"aa", &Id::random()).encode()).await;
+        assert!(matches!(res, Err(Error::IdMismatch)));
+        token.cancel();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pings_according_to_status() {
+        let token = CancellationToken::new();
+        let (task, keepalive) = task(&token);
+        assert_eq!(task.next_ping(), Some(task.created + PING_STARTUP_DELAY));
+        task.stat.send_modify(|s| s.queries = 1);
+        assert_eq!(task.next_ping(), Some(task.queried + LINK_RETRY_INTERVAL));
+        tokio::time::advance(Duration::from_secs(5)).await;
+        task.set_active();
+        assert_eq!(task.next_ping(), Some(task.queried + LINK_RETRY_INTERVAL));
+        task.stat.send_modify(|s| (s.status, s.rx_last) = (Status::Good, Instant::now()));
+        assert_eq!(task.next_ping(), Some(Instant::now() + LINK_QUESTIONABLE_AFTER));
+        keepalive.send_replace(true);
+        assert_eq!(task.next_ping(), Some(task.sent + KEEPALIVE_INTERVAL));
+        task.set_term();
+        assert_eq!(task.next_ping(), None);
+        token.cancel();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn turns_questionable_when_inactive() {
+        let token = CancellationToken::new();
+        let (mut task, _) = task(&token);
+        task.set_good();
+        tokio::time::advance(LINK_QUESTIONABLE_AFTER - Duration::from_secs(1)).await;
+        task.set_questionable();
+        assert_eq!(task.stat.borrow().status, Status::Good);
+        task.set_active();
+        tokio::time::advance(Duration::from_secs(1)).await;
+        task.set_questionable();
+        assert_eq!(task.stat.borrow().status, Status::Good);
+        tokio::time::advance(LINK_QUESTIONABLE_AFTER).await;
+        task.set_questionable();
+        assert_eq!(task.stat.borrow().status, Status::Questionable);
+        task.set_active();
+        assert_eq!(task.stat.borrow().status, Status::Good);
+        token.cancel();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn counts_timeouts_until_failure() {
+        let token = CancellationToken::new();
+        let (mut task, _) = task(&token);
+        let ping = || Command::Ping(CmdPing::new().0);
+        assert!(matches!(task.timeout(ping()), Err(Error::InitTimeout)));
+        assert_eq!(task.stat.borrow().status, Status::Fail);
+        task.set_good();
+        for failures in 1..LINK_FAILURES_MAX {
+            assert!(task.timeout(ping()).is_ok());
+            assert_eq!((task.stat.borrow().status, task.stat.borrow().failures), (Status::Questionable, failures));
+        }
+        task.set_active();
+        assert_eq!(task.stat.borrow().status, Status::Questionable);
+        assert!(task.timeout(ping()).is_ok());
+        assert_eq!(task.stat.borrow().status, Status::Fail);
+        tokio::time::advance(TIMEOUT_TOTAL + Duration::from_secs(1)).await;
+        assert!(matches!(task.timeout(ping()), Err(Error::TotalTimeout)));
+        task.set_good();
+        assert_eq!((task.stat.borrow().status, task.stat.borrow().failures), (Status::Good, 0));
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn sheds_queries_when_full() {
+        let token = CancellationToken::new();
+        let peers = Peers::new(token.clone(), Limits::default());
+        let node = node(Id::random(), &peers);
+        let sock = UdpSocket::bind("[::1]:0").await.unwrap();
+        let SocketAddr::V6(remote) = sock.local_addr().unwrap() else {
+            unreachable!()
+        };
+        let pid = Id::random();
+        let cmds = mpsc::channel(LINK_CMDS_MAX).1;
+        let (stat, stat_) = watch::channel(Stat::new());
+        let mut task = Task::new(node, peers.get(&pid).unwrap(), remote, cmds, stat, watch::channel(false).1);
+        for _ in 0..LINK_QUERIES_MAX {
+            task.qrys.spawn(std::future::pending());
+        }
+        task.rcvd(&Msg::find_node_query(b"aa", &pid, &Id::random()).encode()).await.unwrap();
+        let mut buf = [0u8; RBUF_SIZE];
+        let len = timeout(Duration::from_secs(1), sock.recv(&mut buf)).await.unwrap().unwrap();
+        let msg = Value::decode(&buf[..len], BENCODE_MAX_ALLOCS).unwrap();
+        assert_eq!(msg.get::<&[u8]>(Msg::T), Some(&b"aa"[..]));
+        assert_eq!(msg.get::<(i64, &str)>(Msg::E).map(|e| e.0), Some(202));
+        assert_eq!(stat_.borrow().shed, 1);
+        token.cancel();
+    }
+}
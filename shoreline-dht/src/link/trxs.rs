@@ -3,7 +3,6 @@ use crate::util::check;
 use std::collections::BTreeMap;
 use tokio::sync::watch;
 use tokio::time::{Duration, Instant, sleep_until};
-use crate::constants::*;
 
 use super::cmd::Command;
 
@@ -21,6 +20,7 @@ impl Trxs {
 
     pub fn start<T: Into<Command>>(&mut self, cmd: T) -> u64 {
         self.txid += 1;
+        self.stat.send_modify(|s| s.queries += 1);
         self.queue.insert(self.txid, (Instant::now(), cmd.into()));
         self.set_timeout();
         self.txid
@@ -34,6 +34,10 @@ impl Trxs {
         Some(cmd)
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
     pub async fn timeout_next(&mut self) -> Option<Command> {
         check(!self.queue.is_empty())?;
         sleep_until(self.timeout).await;
@@ -43,28 +47,12 @@ impl Trxs {
     }
 
     fn set_rtt(&mut self, rtt: Duration) {
-        self.stat.send_modify(|x| {
-            let r = 0.5;
-            let f = |d: Duration| d.mul_f32(r) + rtt.mul_f32(1.0 - r);
-            x.rtt = x.rtt.take().map(f).or(Some(rtt));
-        });
+        self.stat.send_modify(|x| x.add_rtt(rtt));
     }
 
     fn set_timeout(&mut self) {
         if let Some((_, (created, _))) = self.queue.first_key_value() {
-            self.timeout = *created + self.current_timeout_duration();
+            self.timeout = *created + self.stat.borrow().rto();
         }
     }
-
-    /// Get the current timeout duration based on the RTT statistics
-    ///
-    /// The timeout duration is calculated as rolling average RTT * TIMEOUT_FACTOR.
-    /// If no RTT statistics are available, a default INIT_TIMEOUT is used.
-    fn current_timeout_duration(&self) -> Duration {
-        self.stat
-            .borrow()
-            .rtt
-            .map(|x| x.mul_f32(TIMEOUT_FACTOR))
-            .unwrap_or(TIMEOUT_INIT)
-    }
 }
@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::net::Ipv6Addr;
 use std::sync::Arc;
 use tokio::sync::watch;
@@ -15,7 +16,7 @@ pub struct Netwatch {
 impl Netwatch {
     const INTERVAL: Duration = Duration::from_secs(10);
 
-    pub fn new() -> Self {
+    pub fn new(policy: Policy) -> Self {
         let (list_, list) = watch::channel(BTreeMap::new());
         let task = tokio::task::spawn(async move {
             let mut interval = interval(Self::INTERVAL);
@@ -29,6 +30,9 @@ impl Netwatch {
                 }
                 let mut addresses = BTreeMap::new();
                 for interface in pnet_datalink::interfaces().iter().filter(|i| i.is_up() && !i.is_loopback()) {
+                    if !policy.allows(&interface.name) {
+                        continue;
+                    }
                     // For each interface only consider the first GUA and ULA address.
                     // These are considered the stable addresses for the interface.
                     let mut gua: bool = false;
@@ -38,11 +42,11 @@ impl Netwatch {
                             ipnetwork::IpNetwork::V6(v6) => {
                                 let mut add = false;
                                 let ip = v6.ip();
-                                if !gua && Netwatch::is_gua(&ip) {
+                                if policy.gua && !gua && Netwatch::is_gua(&ip) {
                                     gua = true;
                                     add = true;
                                 }
-                                if !ula && Netwatch::is_ula(&ip) && v6.prefix() == 64 {
+                                if policy.ula && !ula && Netwatch::is_ula(&ip) && v6.prefix() == 64 {
                                     ula = true;
                                     add = true;
                                 }
@@ -74,16 +78,121 @@ impl Netwatch {
 
     /// Check if the address is a global unicast address
     fn is_gua(ip: &std::net::Ipv6Addr) -> bool {
-        !(ip.is_loopback()
-            || ip.is_unspecified()
-            || ip.is_multicast()
-            || ip.is_unique_local()
-            || ip.is_unicast_link_local())
+        Scope::of(ip) == Scope::Global
     }
 
     /// Check if the address is a unique local address
     fn is_ula(ip: &std::net::Ipv6Addr) -> bool {
-        ip.is_unique_local()
+        Scope::of(ip) == Scope::UniqueLocal
+    }
+}
+
+/// The routing scope of an address
+///
+/// Addresses are only exchanged between nodes of the same scope: Handing out
+/// ULAs to public peers would leak private network topology, and handing out
+/// GUAs to private peers (or vice versa) yields unreachable addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scope {
+    Global,
+    UniqueLocal,
+    Other,
+}
+
+impl Scope {
+    /// Get the scope of the given address
+    pub fn of(ip: &Ipv6Addr) -> Self {
+        if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || ip.is_unicast_link_local() {
+            Self::Other
+        } else if ip.is_unique_local() {
+            Self::UniqueLocal
+        } else {
+            Self::Global
+        }
+    }
+
+    /// Check whether addresses of this scope may be routed at all
+    pub fn is_routable(&self) -> bool {
+        !matches!(self, Self::Other)
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Global => write!(f, "GUA"),
+            Self::UniqueLocal => write!(f, "ULA"),
+            Self::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Rules deciding which interfaces and address scopes carry DHT traffic
+///
+/// Interface names are matched against shell-style patterns (`*` and `?`).
+/// An interface is used if it matches any `include` pattern (or `include` is
+/// empty) and does not match any `exclude` pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub gua: bool,
+    pub ula: bool,
+}
+
+impl Policy {
+    /// Check whether the interface with the given name may carry DHT traffic
+    pub fn allows(&self, name: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|p| Self::matches(p, name));
+        let excluded = self.exclude.iter().any(|p| Self::matches(p, name));
+        included && !excluded
+    }
+
+    /// Match a name against a pattern with `*` (any sequence) and `?` (any character)
+    fn matches(pattern: &str, name: &str) -> bool {
+        let p = pattern.chars().collect::<Vec<_>>();
+        let n = name.chars().collect::<Vec<_>>();
+        let (mut i, mut j) = (0, 0);
+        let mut star: Option<(usize, usize)> = None;
+        while j < n.len() {
+            if i < p.len() && (p[i] == '?' || p[i] == n[j]) {
+                i += 1;
+                j += 1;
+            } else if i < p.len() && p[i] == '*' {
+                star = Some((i, j));
+                i += 1;
+            } else if let Some((si, sj)) = star {
+                i = si + 1;
+                j = sj + 1;
+                star = Some((si, sj + 1));
+            } else {
+                return false;
+            }
+        }
+        p[i..].iter().all(|&c| c == '*')
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self { include: vec![], exclude: vec![], gua: true, ula: true }
+    }
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let include = if self.include.is_empty() { "*".to_string() } else { self.include.join(", ") };
+        let scopes = match (self.gua, self.ula) {
+            (true, true) => "GUA, ULA",
+            (true, false) => "GUA",
+            (false, true) => "ULA",
+            (false, false) => "none",
+        };
+        write!(f, "Include: {}", include)?;
+        if !self.exclude.is_empty() {
+            write!(f, "  Exclude: {}", self.exclude.join(", "))?;
+        }
+        write!(f, "  Scopes: {}", scopes)
     }
 }
 
@@ -1,9 +1,14 @@
 use super::super::{Id, Info};
 use super::super::common::Infos;
+use crate::pcp::Mapping;
+use std::net::SocketAddrV6;
 use tokio::sync::oneshot;
 
 #[derive(Debug)]
 pub enum Command {
     Suggest(Info),
-    FindNode(Id, oneshot::Sender<Infos>),
+    Migrate(Id, Info),
+    FindNode(Id, Info, oneshot::Sender<Infos>),
+    Vote(SocketAddrV6, SocketAddrV6),
+    Mapping(Mapping),
 }
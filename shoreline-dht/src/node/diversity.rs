@@ -0,0 +1,142 @@
+use crate::constants::*;
+use crate::net::Scope;
+use crate::{Id, Link};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::net::{Ipv6Addr, SocketAddrV6};
+use std::sync::Arc;
+use tokio::time::Instant;
+
+/// The prefix lengths subject to diversity limits with the maximum number of
+/// nodes per bucket and per table
+const RULES: [(u8, usize, usize); 2] = [
+    (64, BUCKET_MAX_PER_64, TABLE_MAX_PER_64),
+    (48, BUCKET_MAX_PER_48, TABLE_MAX_PER_48),
+];
+
+/// Check whether adding `addr` to `bucket` keeps the routing table diverse
+///
+/// A single network must not be able to fill buckets (Sybil/eclipse attack),
+/// so the number of global addresses per /64 and /48 prefix is limited in
+/// each bucket and across the table. Local addresses are exempt as they
+/// usually share a prefix.
+pub fn admits(addr: &SocketAddrV6, bucket: usize, table: &BTreeMap<usize, BTreeMap<SocketAddrV6, Arc<Link>>>) -> bool {
+    if Scope::of(addr.ip()) != Scope::Global {
+        return true;
+    }
+    RULES.iter().all(|&(len, bucket_max, table_max)| {
+        let net = prefix(addr.ip(), len);
+        let same = |a: &&SocketAddrV6| prefix(a.ip(), len) == net;
+        let in_bucket = table.get(&bucket).map_or(0, |b| b.keys().filter(same).count());
+        let in_table = table.values().flat_map(|b| b.keys()).filter(same).count();
+        in_bucket < bucket_max && in_table < table_max
+    })
+}
+
+fn prefix(ip: &Ipv6Addr, len: u8) -> Ipv6Addr {
+    Ipv6Addr::from_bits(ip.to_bits() & u128::MAX.checked_shl(128 - len as u32).unwrap_or(0))
+}
+
+/// An address or prefix from which suspiciously many IDs were seen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster {
+    pub net: Ipv6Addr,
+    pub len: u8,
+    /// Number of distinct IDs (at most [CLUSTER_IDS_MAX])
+    pub ids: usize,
+}
+
+impl Display for Cluster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}: {} IDs", self.net, self.len, self.ids)
+    }
+}
+
+/// IDs seen per address, /64 and /48 prefix (first-hand, i.e. from the sender)
+///
+/// More than one ID per address or more than the table may hold per prefix
+/// indicate a [Cluster]. Entries expire after [CLUSTER_EXPIRY].
+#[derive(Debug, Default)]
+pub struct Clusters {
+    nets: BTreeMap<(Ipv6Addr, u8), (BTreeSet<Id>, Instant)>,
+}
+
+impl Clusters {
+    pub fn observe(&mut self, ip: &Ipv6Addr, id: Id) {
+        if Scope::of(ip) != Scope::Global {
+            return;
+        }
+        let now = Instant::now();
+        for len in [128, 64, 48] {
+            let key = (prefix(ip, len), len);
+            if !self.nets.contains_key(&key) && self.nets.len() >= CLUSTER_ENTRIES_MAX {
+                continue;
+            }
+            let (ids, seen) = self.nets.entry(key).or_insert_with(|| (BTreeSet::new(), now));
+            if ids.len() < CLUSTER_IDS_MAX {
+                ids.insert(id);
+            }
+            *seen = now;
+        }
+    }
+
+    /// Forget networks not seen for [CLUSTER_EXPIRY]
+    pub fn prune(&mut self) {
+        self.nets.retain(|_, (_, seen)| seen.elapsed() <= CLUSTER_EXPIRY);
+    }
+
+    /// Get the clusters with the most IDs first
+    pub fn suspicious(&self) -> Vec<Cluster> {
+        let limit = |len| match len {
+            128 => 1,
+            64 => TABLE_MAX_PER_64,
+            _ => TABLE_MAX_PER_48,
+        };
+        let mut clusters = self
+            .nets
+            .iter()
+            .filter(|((_, len), (ids, _))| ids.len() > limit(*len))
+            .map(|((net, len), (ids, _))| Cluster { net: *net, len: *len, ids: ids.len() })
+            .collect::<Vec<_>>();
+        clusters.sort_by_key(|c| std::cmp::Reverse(c.ids));
+        clusters.truncate(CLUSTERS_MAX);
+        clusters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Ipv6Addr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn truncates_to_prefix() {
+        assert_eq!(prefix(&ip("2001:db8:1:2:3::4"), 64), ip("2001:db8:1:2::"));
+        assert_eq!(prefix(&ip("2001:db8:1:2:3::4"), 48), ip("2001:db8:1::"));
+        assert_eq!(prefix(&ip("2001:db8:1:2:3::4"), 128), ip("2001:db8:1:2:3::4"));
+    }
+
+    #[test]
+    fn detects_multiple_ids_per_address() {
+        let mut clusters = Clusters::default();
+        clusters.observe(&ip("2001:db8::1"), Id::random());
+        clusters.observe(&ip("2001:db8::1"), Id::random());
+        clusters.observe(&ip("fd00::1"), Id::random());
+        clusters.observe(&ip("fd00::1"), Id::random());
+        let expected = Cluster { net: ip("2001:db8::1"), len: 128, ids: 2 };
+        assert_eq!(clusters.suspicious(), vec![expected]);
+    }
+
+    #[test]
+    fn detects_crowded_prefix() {
+        let mut clusters = Clusters::default();
+        for i in 0..=TABLE_MAX_PER_64 as u128 {
+            clusters.observe(&Ipv6Addr::from_bits(ip("2001:db8::").to_bits() + i), Id::random());
+        }
+        let nets = clusters.suspicious().into_iter().map(|c| (c.net, c.len)).collect::<Vec<_>>();
+        assert_eq!(nets, vec![(ip("2001:db8::"), 64)]);
+    }
+}
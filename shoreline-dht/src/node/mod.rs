@@ -1,41 +1,109 @@
 mod cmd;
+mod diversity;
+mod reach;
 mod stat;
 mod task;
+mod throttle;
+mod votes;
 
 use self::task::Task;
 use super::{Error, Version};
 use crate::{Id, Info};
 use crate::Peers;
+use crate::bootstrap::Bootstrap;
+use crate::net::Scope;
+use crate::io::{self, SocketOptions};
+use crate::util::socket_bound;
+use crate::pcp::Mapping;
+use crate::constants::*;
+use std::collections::BTreeMap;
 use std::net::SocketAddrV6;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 use tokio::sync::{oneshot, watch};
 use tokio_util::sync::CancellationToken;
 
 pub use self::cmd::Command;
+pub use self::diversity::Cluster;
+pub use self::reach::Reachability;
 pub use self::stat::NodeStat;
 
+/// All [Node]s of the DHT by interface name (see [Nodes](crate::Nodes))
+pub(crate) type Siblings = watch::Receiver<BTreeMap<String, Arc<Node>>>;
+
+/// The channel to each link of a [Node] (and the link's token) by remote address
+type Routes = BTreeMap<SocketAddrV6, (mpsc::Sender<Vec<u8>>, CancellationToken)>;
+
 /// A client for the Mainline DHT network
 #[derive(Debug)]
 pub struct Node {
     id: Id,
     name: String,
     addr: SocketAddrV6,
-    cmds: mpsc::UnboundedSender<Command>,
+    sock: Arc<UdpSocket>,
+    /// Datagrams queued for sending in batches (see [Self::send])
+    outbox: mpsc::Sender<(SocketAddrV6, Vec<u8>)>,
+    /// Socket on an ephemeral port for answering probes (see [Reachability])
+    probe: UdpSocket,
+    /// Sockets currently held (see [Self::sockets])
+    sockets: AtomicUsize,
+    routes: Mutex<Routes>,
+    cmds: mpsc::Sender<Command>,
+    rejected: AtomicU64,
+    unsent: AtomicU64,
+    nat_keepalive: bool,
     stat: watch::Receiver<NodeStat>,
     token: CancellationToken,
 }
 
 impl Node {
     /// Create a new [Node] node with the given [Info]
-    pub fn new(id: Id, name: String, addr: SocketAddrV6, peers: Peers, seeds: watch::Receiver<Vec<SocketAddrV6>>) -> Result<Arc<Self>, Error> {
+    pub(crate) fn new(
+        id: Id,
+        name: String,
+        addr: SocketAddrV6,
+        peers: Peers,
+        boot: Bootstrap,
+        siblings: Siblings,
+        opts: &SocketOptions,
+    ) -> Result<Arc<Self>, Error> {
         let (stat_, stat) = watch::channel(NodeStat::default());
-        let cmds = mpsc::unbounded_channel();
+        let cmds = mpsc::channel(NODE_CMDS_MAX);
         let cmdr = cmds.1;
         let cmds = cmds.0;
         let ctok = peers.ctok().child_token();
-        let this = Arc::new(Self { id, name, addr, cmds, stat, token: ctok });
-        Task::spawn(this.clone(), peers, seeds, stat_, cmdr)?;
+        let sockets = AtomicUsize::new(0);
+        let bind = |addr| {
+            let sock = socket_bound(addr).map_err(Error::Socket)?;
+            sockets.fetch_add(1, Ordering::Relaxed);
+            Ok::<_, Error>(sock)
+        };
+        let sock = Arc::new(bind(addr)?);
+        io::configure(&sock, opts).map_err(Error::Socket)?;
+        let outbox = io::spawn_sender(sock.clone(), NODE_SEND_QUEUE_MAX);
+        let probe = bind(SocketAddrV6::new(*addr.ip(), 0, 0, 0))?;
+        let routes = Mutex::new(BTreeMap::new());
+        let (rejected, unsent) = (AtomicU64::new(0), AtomicU64::new(0));
+        let nat_keepalive = opts.nat_keepalive;
+        let this = Arc::new(Self {
+            id,
+            name,
+            addr,
+            sock,
+            outbox,
+            probe,
+            sockets,
+            routes,
+            cmds,
+            rejected,
+            unsent,
+            nat_keepalive,
+            stat,
+            token: ctok,
+        });
+        Task::spawn(this.clone(), peers, boot, siblings, stat_, cmdr);
         Ok(this)
     }
 
@@ -53,6 +121,94 @@ impl Node {
         &self.addr
     }
 
+    /// Get this node's socket (shared by all its [Link](crate::Link)s)
+    pub(crate) fn socket(&self) -> &UdpSocket {
+        &self.sock
+    }
+
+    /// Queue a datagram to `addr` to be sent from this node's socket
+    ///
+    /// Datagrams are sent in batches along with those of other links. Returns
+    /// `false` (and counts the datagram as unsent) if [NODE_SEND_QUEUE_MAX]
+    /// datagrams are already queued.
+    pub(crate) fn send(&self, addr: &SocketAddrV6, buf: &[u8]) -> bool {
+        let sent = self.outbox.try_send((*addr, buf.to_vec())).is_ok();
+        if !sent {
+            self.unsent.fetch_add(1, Ordering::Relaxed);
+        }
+        sent
+    }
+
+    /// Get the socket probes are answered from (see [Reachability])
+    ///
+    /// The peers never send anything to its port, so datagrams from it only
+    /// arrive if they can be reached unsolicited.
+    pub(crate) fn probe_socket(&self) -> &UdpSocket {
+        &self.probe
+    }
+
+    /// Bind a temporary socket on an ephemeral port of this node's address
+    ///
+    /// The socket is counted in [Self::sockets] until it is dropped.
+    pub(crate) fn temp_socket(&self) -> Result<TempSocket<'_>, std::io::Error> {
+        let sock = socket_bound(SocketAddrV6::new(*self.addr.ip(), 0, 0, 0))?;
+        self.sockets.fetch_add(1, Ordering::Relaxed);
+        Ok(TempSocket { sock, count: &self.sockets })
+    }
+
+    /// Get the number of sockets this node holds
+    ///
+    /// This includes the node and probe sockets as well as temporary ones (e.g.
+    /// for port mapping requests).
+    pub fn sockets(&self) -> usize {
+        self.sockets.load(Ordering::Relaxed)
+    }
+
+    /// Check whether links need to keep NAT mappings open (see [SocketOptions::nat_keepalive])
+    pub(crate) fn nat_keepalive(&self) -> bool {
+        self.nat_keepalive
+    }
+
+    /// Receive all datagrams from `addr` through the returned [Route]
+    ///
+    /// A previous registration for the same address is replaced and its owner is
+    /// cancelled through `token` (there can only be one link per address). The
+    /// route is removed when dropped. At most [LINK_QUEUE_MAX] datagrams are
+    /// queued; further ones are dropped.
+    pub(crate) fn register(self: &Arc<Self>, addr: &SocketAddrV6, token: CancellationToken) -> Route {
+        let (tx, rx) = mpsc::channel(LINK_QUEUE_MAX);
+        let displaced = self.routes.lock().unwrap().insert(*addr, (tx.clone(), token));
+        if let Some((displaced, token)) = displaced
+            && !displaced.is_closed()
+        {
+            log::debug!("Route from {} on {} replaced", addr, self.addr);
+            token.cancel();
+        }
+        Route { node: self.clone(), addr: *addr, tx, rx }
+    }
+
+    /// Forward a datagram from `addr` to the registered receiver (if any)
+    ///
+    /// Returns [None] if no receiver is registered, i.e. the datagram is unsolicited,
+    /// and `Some(false)` if it was dropped because the receiver's queue is full.
+    fn route(&self, addr: &SocketAddrV6, buf: &[u8]) -> Option<bool> {
+        let mut routes = self.routes.lock().unwrap();
+        match routes.get(addr).map(|(tx, _)| tx.try_send(buf.to_vec())) {
+            Some(Ok(())) => Some(true),
+            Some(Err(mpsc::error::TrySendError::Full(_))) => Some(false),
+            Some(Err(mpsc::error::TrySendError::Closed(_))) => {
+                routes.remove(addr);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Get this node's [Scope]
+    pub fn scope(&self) -> Scope {
+        Scope::of(self.addr.ip())
+    }
+
     /// Get this node's [Version] (a.k.a. client identifier)
     pub fn version(&self) -> Version {
         Version::SELF
@@ -60,7 +216,24 @@ impl Node {
 
     /// Get this node's current [NodeStat]
     pub fn stat(&self) -> NodeStat {
-        self.stat.borrow().clone()
+        let mut stat = self.stat.borrow().clone();
+        stat.rejected = self.rejected.load(Ordering::Relaxed);
+        stat.unsent = self.unsent.load(Ordering::Relaxed);
+        stat
+    }
+
+    /// Queue a command for the node task without waiting
+    ///
+    /// Fails with [Error::Overloaded] (and counts the command as rejected) if
+    /// [NODE_CMDS_MAX] commands are already pending.
+    fn command(&self, cmd: Command) -> Result<(), Error> {
+        self.cmds.try_send(cmd).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Error::Overloaded
+            }
+            mpsc::error::TrySendError::Closed(_) => Error::NodeTerminated,
+        })
     }
 
     // ///
@@ -74,7 +247,7 @@ impl Node {
     /// gap in the routing table or is a better candidate than an existing node
     /// (e.g. has lower RTT).
     pub fn suggest(&self, info: &Info) -> Result<(), Error> {
-        self.cmds.send(Command::Suggest(info.clone())).map_err(|_| Error::NodeTerminated)
+        self.command(Command::Suggest(info.clone()))
     }
 
     // /// Search the DHT for a [Id] using A* search
@@ -125,17 +298,145 @@ impl Node {
     //     &self.peers
     // }
 
-    /// Find [Info]s close to the given [Id]
+    /// Find [Info]s close to the given [Id] on behalf of the requester `from`
     ///
-    /// Currently returns a list of up to 8 peers closest to the given id.
-    /// This does not perform any network operations, but is just a lookup in the routing table.
-    pub async fn find(&self, id: &Id) -> Result<Vec<Info>, Error> {
+    /// Returns up to 8 peers closest to the given id from this node's routing table
+    /// and the other nodes of this host (BEP 45).
+    /// Each peer is listed once with an address in the requester's [Scope] (none
+    /// if the requester's scope is not routable). This does not perform any network operations, but is just a lookup in the routing table.
+    /// Fails with [Error::Overloaded] if the node cannot keep up with its commands.
+    pub async fn find(&self, id: &Id, from: &Info) -> Result<Vec<Info>, Error> {
         let (tx, rx) = oneshot::channel();
-        self.cmds.send(Command::FindNode(*id, tx)).map_err(|_| Error::NodeTerminated)?;
+        self.command(Command::FindNode(*id, *from, tx))?;
         rx.await.map(Into::into).map_err(|_| Error::NodeTerminated)
     }
 
+    /// Report that the node at `info.addr` now uses `info.id` instead of `old`
+    ///
+    /// A link to the new ID is established, which displaces the old link. The
+    /// table entry of the old ID is kept until the new link has passed
+    /// [Status::Init](crate::Status::Init) and then replaced by it (see
+    /// [Peer::previous_id](crate::Peer::previous_id)). If it fails, the entry is removed.
+    pub(crate) fn migrate(&self, old: &Id, info: &Info) -> Result<(), Error> {
+        self.command(Command::Migrate(*old, *info))
+    }
+
+    /// Vote on this node's external address as observed by the remote node at `voter`
+    ///
+    /// Only responses to our own queries may count as votes.
+    pub fn vote(&self, voter: &SocketAddrV6, addr: &SocketAddrV6) {
+        let _ = self.command(Command::Vote(*voter, *addr));
+    }
+
+    /// Report the port mapping state of this node
+    pub(crate) fn set_mapping(&self, mapping: Mapping) {
+        let _ = self.command(Command::Mapping(mapping));
+    }
+
     pub fn token(&self) -> &CancellationToken {
         &self.token
     }
+
+    /// Create another (unspawned) task for this node to be driven by tests or the fuzzer
+    #[cfg(any(test, fuzzing))]
+    pub(crate) fn unspawned_task(self: &Arc<Self>, peers: Peers) -> Task {
+        let stat = watch::channel(NodeStat::default()).0;
+        let cmds = mpsc::channel(NODE_CMDS_MAX).1;
+        let boot = Bootstrap::new(watch::channel(vec![]).1);
+        let siblings = watch::channel(BTreeMap::new()).1;
+        Task::new(self.clone(), peers, boot, siblings, stat, cmds)
+    }
+
+    /// Wait until the queued datagrams have been handed to the socket
+    #[cfg(fuzzing)]
+    pub(crate) async fn fuzz_flush(&self) {
+        while self.outbox.capacity() < self.outbox.max_capacity() {
+            tokio::task::yield_now().await;
+        }
+        tokio::task::yield_now().await;
+    }
+}
+
+/// The datagrams from one address received by a [Node] (see [Node::register])
+///
+/// Dropping the route removes it from the node, unless it has been replaced.
+#[derive(Debug)]
+pub(crate) struct Route {
+    node: Arc<Node>,
+    addr: SocketAddrV6,
+    tx: mpsc::Sender<Vec<u8>>,
+    rx: mpsc::Receiver<Vec<u8>>,
+}
+
+impl Route {
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Route {
+    fn drop(&mut self) {
+        let mut routes = self.node.routes.lock().unwrap();
+        if routes.get(&self.addr).is_some_and(|(tx, _)| tx.same_channel(&self.tx)) {
+            routes.remove(&self.addr);
+        }
+    }
+}
+
+/// A temporary socket of a [Node] (see [Node::temp_socket])
+#[derive(Debug)]
+pub(crate) struct TempSocket<'a> {
+    sock: UdpSocket,
+    count: &'a AtomicUsize,
+}
+
+impl std::ops::Deref for TempSocket<'_> {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &UdpSocket {
+        &self.sock
+    }
+}
+
+impl Drop for TempSocket<'_> {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Limits;
+    use crate::util::test;
+
+    #[tokio::test]
+    async fn removes_route_on_drop_unless_replaced() {
+        let token = CancellationToken::new();
+        let node = test::node(Id::random(), &Peers::new(token.clone(), Limits::default()));
+        let addr = "[::1]:9".parse().unwrap();
+        let displaced = CancellationToken::new();
+        let first = node.register(&addr, displaced.clone());
+        assert_eq!(node.route(&addr, b"1"), Some(true));
+        let mut second = node.register(&addr, CancellationToken::new());
+        assert!(displaced.is_cancelled());
+        drop(first);
+        assert_eq!(node.route(&addr, b"2"), Some(true));
+        assert_eq!(second.recv().await, Some(b"2".to_vec()));
+        drop(second);
+        assert_eq!(node.route(&addr, b"3"), None);
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn counts_temporary_sockets() {
+        let token = CancellationToken::new();
+        let node = test::node(Id::random(), &Peers::new(token.clone(), Limits::default()));
+        assert_eq!(node.sockets(), 2);
+        let sock = node.temp_socket().unwrap();
+        assert_eq!(node.sockets(), 3);
+        drop(sock);
+        assert_eq!(node.sockets(), 2);
+        token.cancel();
+    }
 }
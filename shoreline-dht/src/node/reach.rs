@@ -0,0 +1,116 @@
+use crate::constants::*;
+use std::fmt::Display;
+use tokio::time::Instant;
+
+/// Whether remote nodes can reach a [Node](crate::Node) socket unsolicited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reachability {
+    /// Not enough evidence (yet)
+    #[default]
+    Unknown,
+    /// Unsolicited queries have been received recently
+    Open,
+    /// Peers confirmed to have queried us, but nothing arrived
+    Filtered,
+}
+
+impl Display for Reachability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "Unknown"),
+            Self::Open => write!(f, "Open"),
+            Self::Filtered => write!(f, "Filtered"),
+        }
+    }
+}
+
+/// Evidence on the [Reachability] of a node socket
+///
+/// Unsolicited queries (from addresses we did not send anything to recently)
+/// prove the socket to be open. When none arrive for [REACHABILITY_WINDOW],
+/// shoreline peers are asked to query us from another port (probe). After
+/// [REACHABILITY_PROBES] acknowledged probes without any unsolicited query,
+/// the socket is considered filtered.
+#[derive(Debug, Default)]
+pub struct Reach {
+    unsolicited: Option<Instant>,
+    probes: u32,
+}
+
+impl Reach {
+    /// Record the arrival of an unsolicited query
+    pub fn unsolicited(&mut self) {
+        self.unsolicited = Some(Instant::now());
+        self.probes = 0;
+    }
+
+    /// Record a probe started at `start` and acknowledged by the peer
+    ///
+    /// The probe counts unless an unsolicited query arrived in the meantime.
+    pub fn probed(&mut self, start: Instant) {
+        if self.unsolicited.is_none_or(|t| t < start) {
+            self.probes = self.probes.saturating_add(1);
+        }
+    }
+
+    /// Whether a probe shall be sent (no recent evidence of being open)
+    pub fn needs_probe(&self) -> bool {
+        self.unsolicited.is_none_or(|t| t.elapsed() > REACHABILITY_WINDOW)
+    }
+
+    pub fn verdict(&self) -> Reachability {
+        if !self.needs_probe() {
+            Reachability::Open
+        } else if self.probes >= REACHABILITY_PROBES {
+            Reachability::Filtered
+        } else {
+            Reachability::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{Duration, advance};
+
+    #[tokio::test(start_paused = true)]
+    async fn open_while_unsolicited_queries_arrive() {
+        let mut reach = Reach::default();
+        assert!(reach.needs_probe());
+        assert_eq!(reach.verdict(), Reachability::Unknown);
+        reach.unsolicited();
+        assert!(!reach.needs_probe());
+        assert_eq!(reach.verdict(), Reachability::Open);
+        advance(REACHABILITY_WINDOW + Duration::from_secs(1)).await;
+        assert!(reach.needs_probe());
+        assert_eq!(reach.verdict(), Reachability::Unknown);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn filtered_after_acknowledged_probes() {
+        let mut reach = Reach::default();
+        for _ in 0..REACHABILITY_PROBES {
+            assert_eq!(reach.verdict(), Reachability::Unknown);
+            reach.probed(Instant::now());
+        }
+        assert_eq!(reach.verdict(), Reachability::Filtered);
+        reach.unsolicited();
+        assert_eq!(reach.verdict(), Reachability::Open);
+        advance(REACHABILITY_WINDOW + Duration::from_secs(1)).await;
+        assert_eq!(reach.verdict(), Reachability::Unknown);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ignores_probe_answered_by_unsolicited_query() {
+        let mut reach = Reach::default();
+        let start = Instant::now();
+        advance(Duration::from_secs(1)).await;
+        reach.unsolicited();
+        advance(REACHABILITY_WINDOW + Duration::from_secs(1)).await;
+        for _ in 0..REACHABILITY_PROBES {
+            reach.probed(start);
+        }
+        assert_eq!(reach.verdict(), Reachability::Unknown);
+    }
+}
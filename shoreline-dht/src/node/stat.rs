@@ -1,6 +1,11 @@
+use std::net::SocketAddrV6;
 use std::sync::Arc;
 
 use super::super::Error;
+use crate::BootstrapState;
+use crate::Cluster;
+use crate::Reachability;
+use crate::pcp::Mapping;
 
 #[derive(Debug, Clone, Default)]
 pub struct NodeStat {
@@ -8,6 +13,38 @@ pub struct NodeStat {
     pub tx_packets: u64,
     pub rx_bytes: u64,
     pub rx_packets: u64,
+    pub table: usize,
+    pub bootstrap: BootstrapState,
+    /// Number of find_node queries sent to the seeds
+    pub queried: u64,
+    /// Number of responses to these queries
+    pub responded: u64,
+    /// Our external address as reported by the majority of remote nodes
+    pub external: Option<SocketAddrV6>,
+    pub reachability: Reachability,
+    /// Number of unsolicited queries received on the node socket
+    pub unsolicited: u64,
+    pub mapping: Mapping,
+    /// Number of datagrams dropped because a link's queue was full
+    pub dropped: u64,
+    /// Number of commands rejected because the node's queue was full
+    pub rejected: u64,
+    /// Number of datagrams not sent because the node's send queue was full
+    pub unsent: u64,
+    /// Number of datagrams dropped because the source exceeded its rate
+    pub throttled: u64,
+    /// Number of datagrams dropped because the source is banned
+    pub banned: u64,
+    /// Number of unsolicited lookups answered with error 202 (see [THROTTLE_LOOKUP_RATE](crate::THROTTLE_LOOKUP_RATE))
+    pub shed: u64,
+    /// Number of currently banned addresses and prefixes
+    pub bans: usize,
+    /// Number of nodes refused because their prefix is crowded in the table
+    pub crowded: u64,
+    /// Number of nodes that restarted with a new ID on the same address
+    pub migrated: u64,
+    /// Networks suspected of running many nodes (Sybil attack)
+    pub clusters: Vec<Cluster>,
     pub error: Option<Arc<Error>>
 }
 
@@ -1,67 +1,107 @@
 use super::super::common::Infos;
-use super::super::common::Msg;
+use super::super::common::{CompactAddr, Msg};
 use super::super::{Id, Info, Link};
 use super::cmd::Command;
 use super::stat::NodeStat;
-use crate::Error;
+use super::diversity::{self, Clusters};
+use super::reach::{Reach, Reachability};
+use super::throttle::{Throttle, Verdict};
+use super::votes::Votes;
 use crate::Node;
+use crate::node::Siblings;
 use crate::Peers;
+use crate::Status;
+use crate::bootstrap::{Bootstrap, BootstrapState, SeedQueries};
 use crate::constants::*;
-use crate::util::socket_bound;
+use crate::io::{Batch, recv_batch, send_batch};
+use crate::net::Scope;
 use bencode_minimal::Value;
-use std::collections::BTreeMap;
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::SocketAddrV6;
 use std::sync::Arc;
-use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 use tokio::sync::watch;
 use tokio::task::JoinSet;
-use tokio::time::{Interval, interval};
+use tokio::time::{Instant, Interval, interval, sleep};
 
 pub struct Task {
     node: Arc<Node>,
-    sock: UdpSocket,
     stat: watch::Sender<NodeStat>,
-    cmds: mpsc::UnboundedReceiver<Command>,
+    cmds: mpsc::Receiver<Command>,
     intvl: Interval,
     peers: Peers,
+    boot: Bootstrap,
+    siblings: Siblings,
+    boot_intvl: Interval,
+    boot_attempts: u32,
+    seed_queries: SeedQueries,
     seeds: watch::Receiver<Vec<SocketAddrV6>>,
     table: BTreeMap<usize, BTreeMap<SocketAddrV6, Arc<Link>>>,
     infos: JoinSet<Infos>,
     terms: JoinSet<Arc<Link>>,
+    migrations: JoinSet<(Arc<Link>, Arc<Link>)>,
+    migrating: BTreeSet<SocketAddrV6>,
+    votes: Votes,
+    reach: Reach,
+    reach_intvl: Interval,
+    probes: JoinSet<Option<Instant>>,
+    contacted: BTreeMap<SocketAddrV6, Instant>,
+    throttle: Throttle,
+    clusters: Clusters,
 }
 
 impl Task {
     pub fn spawn(
         node: Arc<Node>,
         peers: Peers,
-        seeds: watch::Receiver<Vec<SocketAddrV6>>,
+        boot: Bootstrap,
+        siblings: Siblings,
         stat: watch::Sender<NodeStat>,
-        cmds: mpsc::UnboundedReceiver<Command>,
-    ) -> Result<(), Error> {
-        let sock = socket_bound(*node.addr()).map_err(Error::Socket)?;
-        let this = Self {
+        cmds: mpsc::Receiver<Command>,
+    ) {
+        let this = Self::new(node, peers, boot, siblings, stat, cmds);
+        tokio::task::spawn(Box::new(this).run());
+    }
+
+    pub fn new(
+        node: Arc<Node>,
+        peers: Peers,
+        boot: Bootstrap,
+        siblings: Siblings,
+        stat: watch::Sender<NodeStat>,
+        cmds: mpsc::Receiver<Command>,
+    ) -> Self {
+        Self {
             node,
-            sock,
             stat,
             cmds,
             intvl: interval(REFRESH_INTERVAL),
             peers,
-            seeds,
+            seeds: boot.seeds().clone(),
+            boot,
+            siblings,
+            boot_intvl: interval(BOOTSTRAP_INTERVAL),
+            boot_attempts: 0,
+            seed_queries: SeedQueries::default(),
             table: BTreeMap::new(),
             infos: JoinSet::new(),
             terms: JoinSet::new(),
-        };
-        tokio::task::spawn(Box::new(this).run());
-        Ok(())
+            migrations: JoinSet::new(),
+            migrating: BTreeSet::new(),
+            votes: Votes::default(),
+            reach: Reach::default(),
+            reach_intvl: interval(REACHABILITY_INTERVAL),
+            probes: JoinSet::new(),
+            contacted: BTreeMap::new(),
+            throttle: Throttle::default(),
+            clusters: Clusters::default(),
+        }
     }
 
     /// The main loop of the node task
     ///
     /// This function runs until the node is shut down or an error occurs.
     async fn run(mut self: Box<Self>) {
-        self.seed().await;
         self.run_loop().await;
 
         for bucket in self.table.into_values() {
@@ -72,38 +112,83 @@ impl Task {
     }
 
     async fn run_loop(&mut self) {
-        let mut rbuf = vec![0u8; RBUF_SIZE];
+        let mut rbatch = Batch::new();
+        let mut sbatch = Batch::new();
         let mut sbuf = vec![0u8; RBUF_SIZE];
 
         loop {
             tokio::select! {
-                res = self.sock.recv_from(&mut rbuf) => {
-                    if let Ok((len, SocketAddr::V6(addr))) = res {
+                res = recv_batch(self.node.socket(), &mut rbatch) => {
+                    if res.is_err() {
+                        continue;
+                    }
+                    for (addr, rbuf) in rbatch.iter() {
+                        match self.route(&addr, rbuf) {
+                            Some(true) => continue,
+                            Some(false) => {
+                                self.stat.send_modify(|s| s.dropped += 1);
+                                continue;
+                            }
+                            None => (),
+                        }
                         self.stat.send_modify(|s| s.add_rx_bytes(rbuf.len() as u64));
                         sbuf.clear();
-                        self.dispatch(addr, &rbuf[..len], &mut sbuf).await;
+                        self.dispatch(addr, rbuf, &mut sbuf).await;
+                        if !sbuf.is_empty() {
+                            sbatch.push(addr, &sbuf);
+                        }
                     }
+                    self.flush(&mut sbatch).await;
                 }
                 Some(cmd) = self.cmds.recv() => {
                     match cmd {
-                        Command::FindNode(id, tx) => {
-                            let infos = self.find(&id);
+                        Command::FindNode(id, from, tx) => {
+                            let infos = self.find(&id, &from);
                             let _ = tx.send(infos);
                         }
                         Command::Suggest(info) => self.suggest(info),
+                        Command::Migrate(old, info) => self.migrate(old, info),
+                        Command::Vote(voter, addr) => self.vote(voter, addr),
+                        Command::Mapping(mapping) => self.stat.send_modify(|s| s.mapping = mapping),
                     }
                 }
                 Some(res) = self.infos.join_next() => {
                     res.unwrap().iter().for_each(|i| self.suggest(*i));
                 }
                 Some(res) = self.terms.join_next() => {
-                    self.remove(res.unwrap());
+                    let link = res.unwrap();
+                    if !self.migrating.contains(link.addr()) {
+                        self.remove(link);
+                    }
+                }
+                Some(res) = self.migrations.join_next() => {
+                    let (old, new) = res.unwrap();
+                    self.migrated(old, new);
+                }
+                Some(res) = self.probes.join_next() => {
+                    if let Some(start) = res.unwrap() {
+                        self.reach.probed(start);
+                    }
+                    self.set_reachability();
+                }
+                _ = self.reach_intvl.tick() => {
+                    self.check_reachability();
+                }
+                _ = self.boot_intvl.tick(), if !self.is_bootstrapped() => {
+                    self.bootstrap().await;
                 }
                 Ok(()) = self.seeds.changed() => {
                     self.seed().await;
                 }
                 _ = self.intvl.tick() => {
                     self.refresh();
+                    self.keepalive();
+                    self.throttle.prune(Instant::now());
+                    let bans = self.throttle.bans(Instant::now());
+                    self.stat.send_if_modified(|s| std::mem::replace(&mut s.bans, bans) != bans);
+                    self.clusters.prune();
+                    let clusters = self.clusters.suspicious();
+                    self.stat.send_if_modified(|s| std::mem::replace(&mut s.clusters, clusters.clone()) != clusters);
                 }
                 _ = self.node.token().cancelled() => {
                     break;
@@ -112,35 +197,213 @@ impl Task {
         }
     }
 
+    fn is_bootstrapped(&self) -> bool {
+        self.stat.borrow().bootstrap == BootstrapState::Done
+    }
+
+    /// Advance the bootstrap phase
+    ///
+    /// The seeds are queried up to [BOOTSTRAP_SEED_ATTEMPTS] times. If none of
+    /// them yields a good node, the persisted nodes are tried as well (once) while
+    /// the seeds continue to be queried. Bootstrapping is done as soon as the table
+    /// contains a good node.
+    async fn bootstrap(&mut self) {
+        if self.table.values().flat_map(|b| b.values()).any(|l| l.stat().borrow().status.is_good()) {
+            self.stat.send_modify(|s| s.bootstrap = BootstrapState::Done);
+            self.boot.set_ready();
+            return;
+        }
+        self.boot_attempts += 1;
+        if self.boot_attempts > BOOTSTRAP_SEED_ATTEMPTS && self.stat.borrow().bootstrap == BootstrapState::Seeding {
+            let scope = self.node.scope();
+            let infos = self.boot.cache();
+            let infos = infos.into_iter().filter(|i| Scope::of(i.addr.ip()) == scope).collect::<Vec<_>>();
+            self.stat.send_modify(|s| s.bootstrap = BootstrapState::Fallback);
+            infos.into_iter().for_each(|i| self.suggest(i));
+        }
+        self.seed().await;
+    }
+
     async fn seed(&mut self) {
+        let scope = self.node.scope();
         let addrs = self.seeds.borrow().clone();
-        for addr in addrs.into_iter() {
+        for addr in addrs.into_iter().filter(|a| Scope::of(a.ip()) == scope) {
             let target = Id::random();
-            let buf = Msg::find_node_query(&[0], self.node.id(), &target).encode();
-            self.send(&buf, addr).await;
+            let t = self.seed_queries.start(addr);
+            let buf = Msg::find_node_query(&t, self.node.id(), &target).encode();
+            if self.send(&buf, addr).await.is_some() {
+                self.stat.send_modify(|s| s.queried += 1);
+            }
         }
     }
 
+    /// Suggest a node for the routing table
+    ///
+    /// The node is handed to the sibling [Node] best suited to reach it (see
+    /// [Self::best_local]). Nodes outside the [Scope] of all local nodes are ignored
+    /// as they are either unreachable or must not be exposed to the peers of a scope.
+    /// A peer is only added once (BEP 45), even if known under several addresses,
+    /// and our own nodes (see [Self::is_local]) never. Blocklisted addresses are ignored, and so are addresses whose prefix is
+    /// already crowded in the table (see [diversity::admits]). A full bucket
+    /// only takes the node if it contains a bad (failed) node to replace (BEP 5),
+    /// which is only evicted once the new node has been admitted.
     fn suggest(&mut self, info: Info) {
-        if &info.id != self.node.id() && !info.id.is_null() {
-            let bucket = self.node.id().similarity(&info.id);
-            let bucket = self.table.entry(bucket).or_default();
-            if !bucket.contains_key(&info.addr) && bucket.len() < BUCKET_MAX_LEN {
-                let peer = self.peers.get(&info.id);
-                let link = peer.connect(&self.node, &info.addr);
-                bucket.insert(info.addr, link.clone());
-                self.terms.spawn(async move {
-                    link.token().cancelled().await;
-                    link
-                });
+        if self.is_local(&info.id) || info.id.is_null() || self.peers.is_blocked(info.addr.ip()) {
+            return;
+        }
+        match self.best_local(&info.addr) {
+            Some(node) if node.addr() != self.node.addr() => {
+                let _ = node.suggest(&info);
+                return;
+            }
+            Some(_) => (),
+            None => return,
+        }
+        let index = self.node.id().similarity(&info.id);
+        let bucket = self.table.entry(index).or_default();
+        let known = bucket.values().any(|l| l.peer().id() == &info.id);
+        if known || bucket.contains_key(&info.addr) {
+            return;
+        }
+        let mut bad = None;
+        if bucket.len() >= BUCKET_MAX_LEN {
+            match bucket.values().find(|l| l.stat().borrow().status.is_expendable()) {
+                Some(link) => bad = Some(link.clone()),
+                None => return,
             }
         }
+        if !diversity::admits(&info.addr, index, &self.table) {
+            self.stat.send_modify(|s| s.crowded += 1);
+            return;
+        }
+        if let Some(bad) = bad {
+            self.remove(bad);
+        }
+        let Ok(link) = self.peers.connect(&info.id, &self.node, &info.addr) else {
+            return;
+        };
+        self.table.entry(index).or_default().insert(info.addr, link.clone());
+        self.stat.send_modify(|s| s.table += 1);
+        self.terms.spawn(async move {
+            link.token().cancelled().await;
+            link
+        });
+    }
+
+    /// Start replacing the entry of a node that changed its ID from `old` to `info.id`
+    ///
+    /// The link to the new ID displaces the old link, but the old entry stays in
+    /// the table (and keeps its place) until the new link has passed
+    /// [Status::Init] (see [Self::migrated]). Only entries in the table are
+    /// migrated, and only once at a time.
+    fn migrate(&mut self, old: Id, info: Info) {
+        let index = self.node.id().similarity(&old);
+        let link = self.table.get(&index).and_then(|b| b.get(&info.addr));
+        let Some(link) = link.filter(|l| l.peer().id() == &old).cloned() else {
+            return;
+        };
+        if !self.migrating.insert(info.addr) {
+            return;
+        }
+        let Ok(new) = self.peers.connect(&info.id, &self.node, &info.addr) else {
+            self.migrating.remove(&info.addr);
+            link.token().cancel();
+            return;
+        };
+        log::info!("Node {} changed ID from {} to {}", info.addr, old, info.id);
+        self.migrations.spawn(async move {
+            let _ = new.init().await;
+            (link, new)
+        });
+    }
+
+    /// Replace the entry of `old` by `new` if it has been established
+    ///
+    /// The new link records the old ID on its peer. If it failed, the old
+    /// entry is removed all the same as its link has been displaced.
+    fn migrated(&mut self, old: Arc<Link>, new: Arc<Link>) {
+        self.migrating.remove(old.addr());
+        self.remove(old.clone());
+        if new.stat().borrow().status.is_expendable() {
+            log::debug!("Node {} failed after changing its ID", new.addr());
+            return;
+        }
+        new.peer().set_previous_id(*old.peer().id());
+        self.stat.send_modify(|s| s.migrated += 1);
+        self.suggest(Info::new(*new.peer().id(), *new.addr()));
+    }
+
+    /// Check whether `id` is the [Id] of this node or one of its siblings
+    fn is_local(&self, id: &Id) -> bool {
+        self.node.id() == id || self.siblings.borrow().values().any(|n| n.id() == id)
     }
 
+    /// Find the local node best suited to reach `addr`
+    ///
+    /// Candidates are this node and its siblings in the same [Scope] as `addr`.
+    /// The one sharing the longest prefix with `addr` wins (this node on ties).
+    fn best_local(&self, addr: &SocketAddrV6) -> Option<Arc<Node>> {
+        let siblings = self.siblings.borrow();
+        let locals = std::iter::once(&self.node).chain(siblings.values());
+        nearest(addr, locals, |n| *n.addr()).cloned()
+    }
+
+    /// Count the vote of `voter` on our external address and update the stats
+    fn vote(&mut self, voter: SocketAddrV6, addr: SocketAddrV6) {
+        self.votes.vote(&voter, addr, Instant::now());
+        let external = self.votes.winner();
+        self.stat.send_if_modified(|s| {
+            let modified = s.external != external;
+            s.external = external;
+            modified
+        });
+    }
+
+    /// Update the reachability verdict and probe if there is no recent evidence
+    ///
+    /// Probes are sent to a random good shoreline peer, one at a time.
+    fn check_reachability(&mut self) {
+        self.contacted.retain(|_, t| t.elapsed() <= REACHABILITY_CONTACT_EXPIRY);
+        self.set_reachability();
+        if !self.reach.needs_probe() || !self.probes.is_empty() {
+            return;
+        }
+        let links = self.table.values().flat_map(|b| b.values()).filter(|l| {
+            let stat = l.stat().borrow();
+            stat.status.is_good() && stat.version.is_some_and(|v| v.is_shoreline())
+        });
+        let links = links.cloned().collect::<Vec<_>>();
+        if links.is_empty() {
+            return;
+        }
+        let link = links[rand::random::<u32>() as usize % links.len()].clone();
+        self.probes.spawn(async move {
+            let start = Instant::now();
+            link.probe().await.ok()?;
+            sleep(REACHABILITY_PROBE_GRACE).await;
+            Some(start)
+        });
+    }
+
+    fn set_reachability(&mut self) {
+        let reachability = self.reach.verdict();
+        self.stat.send_if_modified(|s| {
+            let modified = s.reachability != reachability;
+            s.reachability = reachability;
+            modified
+        });
+    }
+
+    /// Remove `link` from the table (unless its address has been taken over by another link)
     fn remove(&mut self, link: Arc<Link>) {
-        let bucket = self.node.id().similarity(link.peer().id());
-        if let Some(link) = self.table.get_mut(&bucket).and_then(|b| b.remove(link.addr())) {
+        let index = self.node.id().similarity(link.peer().id());
+        let Some(bucket) = self.table.get_mut(&index) else {
+            return;
+        };
+        if bucket.get(link.addr()).is_some_and(|l| Arc::ptr_eq(l, &link)) {
+            bucket.remove(link.addr());
             link.token().cancel();
+            self.stat.send_modify(|s| s.table -= 1);
         }
     }
 
@@ -172,28 +435,67 @@ impl Task {
         } else {
             *self.node.id()
         };
-        if let Some(peer) = self.random() {
+        if let Some(link) = self.random() {
+            let peer = link.peer().clone();
             self.infos.spawn(async move { peer.find_node(&id).await.unwrap_or_default() });
         }
     }
 
-    fn find(&self, target: &Id) -> Infos {
-        let mut infos = vec![];
-        let start = self.node.id().similarity(target);
-        for bucket in start..160 {
-            if let Some(b) = self.table.get(&bucket) {
-                for link in b.values() {
-                    infos.push(Info::new(*link.peer().id(), *link.addr()));
-                    if infos.len() >= 8 {
-                        break;
-                    }
-                }
+    /// Keep the NAT mappings of the table's links open unless the node is reachable
+    fn keepalive(&self) {
+        let on = self.node.nat_keepalive() && self.reach.verdict() != Reachability::Open;
+        self.table.values().flat_map(|b| b.values()).for_each(|l| l.set_keepalive(on));
+    }
+
+    /// Find the nodes closest to `target` to be handed out to the requester `from`
+    ///
+    /// The candidates are the links in this node's table and the sibling nodes,
+    /// which advertise the other addresses of this host (BEP 45, see [lookup]).
+    /// The table holds each peer only once (see [Self::suggest]), so the result
+    /// does not list a peer under several addresses.
+    fn find(&self, target: &Id, from: &Info) -> Infos {
+        let links = self.table.values().flat_map(|b| b.values());
+        let links = links.map(|l| (Info::new(*l.peer().id(), *l.addr()), l.stat().borrow().status));
+        let siblings = self.siblings.borrow();
+        let locals = siblings.values().filter(|n| n.addr() != self.node.addr());
+        lookup(target, from, links, locals.map(|n| Info::new(*n.id(), *n.addr())))
+    }
+
+    /// Forward a datagram to the link for its source (see [Node::route])
+    ///
+    /// Responses to pending seed queries are never forwarded (returning [None])
+    /// as the link does not know their transaction, even if the seed is linked.
+    fn route(&self, addr: &SocketAddrV6, rbuf: &[u8]) -> Option<bool> {
+        if self.seed_queries.is_pending(addr) {
+            let v = Value::decode(rbuf, BENCODE_MAX_ALLOCS);
+            let t = v.as_ref().and_then(|v| v.get::<&[u8]>(Msg::T));
+            if t.is_some_and(|t| self.seed_queries.matches(addr, t)) {
+                return None;
             }
         }
-        Infos::from(infos)
+        self.node.route(addr, rbuf)
     }
 
-    async fn dispatch(&mut self, addr: std::net::SocketAddrV6, rbuf: &[u8], sbuf: &mut Vec<u8>) -> Option<()> {
+    /// Handle a datagram not routed to any link and write the reply (if any) to `sbuf`
+    ///
+    /// Datagrams from blocklisted sources or sources exceeding their rate (see
+    /// [Throttle]) are dropped. Lookups exceeding the rate of the node as a whole
+    /// are answered with error 202 (see [Throttle::lookup]).
+    async fn dispatch(&mut self, addr: SocketAddrV6, rbuf: &[u8], sbuf: &mut Vec<u8>) -> Option<()> {
+        if self.peers.is_blocked(addr.ip()) {
+            return None;
+        }
+        match self.throttle.check(addr.ip(), Instant::now()) {
+            Verdict::Allow => (),
+            Verdict::Throttle => {
+                self.stat.send_modify(|s| s.throttled += 1);
+                return None;
+            }
+            Verdict::Ban => {
+                self.stat.send_modify(|s| s.banned += 1);
+                return None;
+            }
+        }
         let v = Value::decode(rbuf, BENCODE_MAX_ALLOCS)?;
 
         match v.get::<&str>(Msg::Y)? {
@@ -202,30 +504,53 @@ impl Task {
                 let a = v.get::<&Value>(Msg::A)?;
                 let t = v.get::<&[u8]>(Msg::T)?;
                 let id = a.get::<Id>(Msg::ID)?;
+                let from = Info::new(id, addr);
+                self.clusters.observe(addr.ip(), id);
+                let ip = CompactAddr::encode(&addr);
+                if self.contacted.get(&addr).is_none_or(|t| t.elapsed() > REACHABILITY_CONTACT_EXPIRY) {
+                    self.reach.unsolicited();
+                    self.stat.send_modify(|s| s.unsolicited += 1);
+                    self.set_reachability();
+                }
                 match q {
                     Msg::PING => {
-                        Msg::ping_response(t, self.node.id()).encode_into(sbuf);
+                        Msg::ping_response(t, &ip, self.node.id()).encode_into(sbuf);
+                    }
+                    Msg::FIND_NODE | Msg::GET_PEERS if !self.throttle.lookup(Instant::now()) => {
+                        self.stat.send_modify(|s| s.shed += 1);
+                        Msg::error_202(t, &ip).encode_into(sbuf);
                     }
                     Msg::FIND_NODE => {
                         let target = a.get::<Id>(Msg::TARGET)?;
-                        let nodes6 = self.find(&target).encode();
-                        Msg::find_node_response(t, self.node.id(), &nodes6).encode_into(sbuf);
+                        let nodes6 = self.find(&target, &from).encode();
+                        Msg::find_node_response(t, &ip, self.node.id(), &nodes6).encode_into(sbuf);
                     }
                     Msg::GET_PEERS => {
                         let info_hash = a.get::<Id>(Msg::INFO_HASH)?;
-                        let nodes6 = self.find(&info_hash).encode();
+                        let nodes6 = self.find(&info_hash, &from).encode();
                         let token = Msg::TOKEN_VALUE.as_bytes();
-                        Msg::get_peers_response(t, self.node.id(), token, &nodes6).encode_into(sbuf);
+                        Msg::get_peers_response(t, &ip, self.node.id(), token, &nodes6).encode_into(sbuf);
                     }
                     Msg::ANNOUNCE_PEER => {
-                        Msg::announce_peer_response(t, self.node.id()).encode_into(sbuf);
+                        Msg::announce_peer_response(t, &ip, self.node.id()).encode_into(sbuf);
                     }
                     _ => (),
                 }
-                self.suggest(Info::new(id, addr));
+                self.suggest(from);
             }
             Msg::R => {
                 let r = v.get::<&Value>(Msg::R)?;
+                let t = v.get::<&[u8]>(Msg::T)?;
+                let ip = v.get::<&[u8]>(Msg::IP).and_then(CompactAddr::decode);
+                if self.seed_queries.resolve(&addr, t) {
+                    self.stat.send_modify(|s| s.responded += 1);
+                    if let Some(ip) = ip {
+                        self.vote(addr, ip);
+                    }
+                }
+                if let Some(id) = r.get::<Id>(Msg::ID) {
+                    self.clusters.observe(addr.ip(), id);
+                }
                 if let Some(infos) = r.get::<&[u8]>(Msg::NODES6).and_then(Infos::decode) {
                     infos.iter().for_each(|info| self.suggest(*info));
                 }
@@ -233,16 +558,305 @@ impl Task {
             _ => (),
         };
 
-        if !sbuf.is_empty() {
-            self.send(sbuf, addr).await?;
-        }
-
         Some(())
     }
 
+    /// Send the replies collected in `batch`
+    async fn flush(&mut self, batch: &mut Batch) {
+        if batch.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let mut len = 0;
+        for (addr, buf) in batch.iter() {
+            self.contacted.insert(addr, now);
+            len += buf.len() as u64;
+        }
+        if send_batch(self.node.socket(), batch).await.is_ok() {
+            self.stat.send_modify(|s| s.add_tx_bytes(len));
+        }
+    }
+
     async fn send(&mut self, sbuf: &[u8], addr: SocketAddrV6) -> Option<()> {
-        let len = self.sock.send_to(&sbuf, addr).await.ok()?;
+        let len = self.node.socket().send_to(sbuf, addr).await.ok()?;
+        self.contacted.insert(addr, Instant::now());
         self.stat.send_modify(|s| s.add_tx_bytes(len as u64));
         Some(())
     }
 }
+
+#[cfg(fuzzing)]
+impl Task {
+    /// Feed a datagram received from `addr` into [Self::dispatch] and return the reply (if any)
+    pub async fn fuzz_dispatch(&mut self, addr: SocketAddrV6, buf: &[u8]) -> Vec<u8> {
+        let mut sbuf = vec![];
+        self.dispatch(addr, buf, &mut sbuf).await;
+        sbuf
+    }
+}
+
+/// Select the candidate whose address shares the longest prefix with `addr`
+///
+/// Only candidates in the same [Scope] as `addr` are considered. On ties the
+/// first one wins.
+fn nearest<T>(
+    addr: &SocketAddrV6,
+    candidates: impl IntoIterator<Item = T>,
+    f: impl Fn(&T) -> SocketAddrV6,
+) -> Option<T> {
+    let scope = Scope::of(addr.ip());
+    let prefix = |a: SocketAddrV6| (a.ip().to_bits() ^ addr.ip().to_bits()).leading_zeros();
+    let mut best: Option<(u32, T)> = None;
+    for c in candidates {
+        let local = f(&c);
+        if Scope::of(local.ip()) == scope && best.as_ref().is_none_or(|(p, _)| prefix(local) > *p) {
+            best = Some((prefix(local), c));
+        }
+    }
+    best.map(|(_, c)| c)
+}
+
+/// Select the nodes to hand out to the requester `from` (see [Task::find])
+///
+/// Only candidates in the requester's [Scope] are considered, none at all if it
+/// is not routable (e.g. link-local). The `locals` (our other nodes) are good
+/// nodes and compete with the table's `links` (see [closest]).
+fn lookup(
+    target: &Id,
+    from: &Info,
+    links: impl IntoIterator<Item = (Info, Status)>,
+    locals: impl IntoIterator<Item = Info>,
+) -> Infos {
+    let scope = Scope::of(from.addr.ip());
+    if !scope.is_routable() {
+        return Infos::default();
+    }
+    let candidates = links.into_iter().chain(locals.into_iter().map(|i| (i, Status::Good)));
+    closest(target, from, candidates.filter(|(i, _)| Scope::of(i.addr.ip()) == scope))
+}
+
+/// Select the [FIND_NODE_MAX_LEN] candidates closest to `target` for the requester `from`
+///
+/// The requester is excluded (by ID and address), and so are failed nodes. Good
+/// nodes are preferred over the others, which only fill up the result. The result
+/// is sorted by XOR distance to `target` (closest first).
+fn closest(target: &Id, from: &Info, candidates: impl IntoIterator<Item = (Info, Status)>) -> Infos {
+    let mut infos = candidates
+        .into_iter()
+        .filter(|(i, status)| i.id != from.id && i.addr != from.addr && !status.is_expendable())
+        .map(|(i, status)| (!status.is_good(), i.id.xor(target), i))
+        .collect::<Vec<_>>();
+    infos.sort_unstable_by_key(|(bad, distance, _)| (*bad, *distance));
+    infos.truncate(FIND_NODE_MAX_LEN);
+    infos.sort_unstable_by_key(|(_, distance, _)| *distance);
+    Infos::from(infos.into_iter().map(|(.., i)| i).collect::<Vec<_>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test;
+
+    fn info(id: Id, port: u16) -> Info {
+        Info::new(id, SocketAddrV6::new("2001:db8::1".parse().unwrap(), port, 0, 0))
+    }
+
+    /// An ID whose XOR distance to [Id::UNKNOWN] grows with `n`
+    fn id(n: u8) -> Id {
+        let mut bytes = [0u8; Id::BYTES];
+        bytes[Id::BYTES - 1] = n;
+        Id::from_bytes(&bytes)
+    }
+
+    fn ports(infos: Infos) -> Vec<u16> {
+        infos.iter().map(|i| i.addr.port()).collect()
+    }
+
+    #[test]
+    fn sorts_closest_by_distance() {
+        let from = info(id(100), 100);
+        let candidates = [3, 1, 2].map(|n| (info(id(n), n as u16), Status::Good));
+        assert_eq!(ports(closest(&Id::UNKNOWN, &from, candidates)), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn excludes_requester_and_failed_nodes() {
+        let from = info(id(100), 100);
+        let candidates = [
+            (info(id(1), 1), Status::Fail),
+            (info(id(2), 2), Status::Term),
+            (info(id(100), 3), Status::Good),
+            (info(id(4), 100), Status::Good),
+            (info(id(5), 5), Status::Init),
+        ];
+        assert_eq!(ports(closest(&Id::UNKNOWN, &from, candidates)), vec![5]);
+    }
+
+    #[test]
+    fn prefers_good_nodes_and_caps_result() {
+        let from = info(id(200), 200);
+        let n = FIND_NODE_MAX_LEN as u8;
+        let init = (1..=n).map(|n| (info(id(n), n as u16), Status::Init));
+        let good = (n + 1..=2 * n).map(|n| (info(id(n), n as u16), Status::Good));
+        let result = ports(closest(&Id::UNKNOWN, &from, init.chain(good)));
+        assert_eq!(result, (n as u16 + 1..=2 * n as u16).collect::<Vec<_>>());
+        let status = |n| if n > 2 { Status::Questionable } else { Status::Good };
+        let some_good = (1..=n + 2).map(|n| (info(id(n), n as u16), status(n)));
+        assert_eq!(ports(closest(&Id::UNKNOWN, &from, some_good)), (1..=n as u16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn nearest_prefers_longest_prefix_in_scope() {
+        let addr = |s: &str| SocketAddrV6::new(s.parse().unwrap(), 6881, 0, 0);
+        let locals = ["fd00::1", "2001:db8:2::1", "2001:db8:1::1", "2001:db8:1::2"].map(addr);
+        assert_eq!(nearest(&addr("2001:db8:1::5"), locals, |a| *a), Some(addr("2001:db8:1::1")));
+        assert_eq!(nearest(&addr("fd00:1::1"), locals, |a| *a), Some(addr("fd00::1")));
+        assert_eq!(nearest(&addr("::1"), locals, |a| *a), None);
+    }
+
+    #[test]
+    fn lookup_advertises_local_nodes_in_scope() {
+        let info = |n: u8, ip: &str| Info::new(id(n), SocketAddrV6::new(ip.parse().unwrap(), n as u16, 0, 0));
+        let links = [
+            (info(1, "fd00::1"), Status::Questionable),
+            (info(2, "2001:db8::2"), Status::Good),
+        ];
+        let locals = [info(3, "fd00::3"), info(4, "2001:db8::4")];
+        let from = info(100, "fd00::100");
+        assert_eq!(ports(lookup(&Id::UNKNOWN, &from, links, locals)), vec![1, 3]);
+        let from = info(100, "2001:db8::100");
+        assert_eq!(ports(lookup(&Id::UNKNOWN, &from, links, locals)), vec![2, 4]);
+        let from = info(100, "fe80::100");
+        assert!(lookup(&Id::UNKNOWN, &from, links, locals).is_empty());
+    }
+
+    #[tokio::test]
+    async fn suggest_ignores_local_nodes() {
+        let token = tokio_util::sync::CancellationToken::new();
+        let peers = Peers::new(token.clone(), crate::Limits::default());
+        let ((node, mut task), sibling) = (node(id(200), &peers), test::node(id(201), &peers));
+        let siblings = BTreeMap::from([("a".to_string(), node.clone()), ("b".to_string(), sibling)]);
+        task.siblings = watch::channel(siblings).1;
+        let addr = SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, 1, 0, 0);
+        task.suggest(Info::new(id(200), addr));
+        task.suggest(Info::new(id(201), addr));
+        assert_eq!(task.count(), 0);
+        task.suggest(Info::new(id(1), addr));
+        assert_eq!(task.count(), 1);
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn find_considers_table_links_only() {
+        let remote = |port| SocketAddrV6::new("fd00::1".parse().unwrap(), port, 0, 0);
+        let token = tokio_util::sync::CancellationToken::new();
+        let peers = Peers::new(token.clone(), crate::Limits::default());
+        let ((node, mut task), (other, _)) = (node(id(200), &peers), node(id(200), &peers));
+        for n in [1, 2, 3] {
+            let link = peers.connect(&id(n), &node, &remote(n as u16)).unwrap();
+            task.table.entry(node.id().similarity(&id(n))).or_default().insert(*link.addr(), link);
+        }
+        let _ = peers.connect(&id(4), &other, &remote(4)).unwrap();
+        let from = Info::new(id(100), remote(2));
+        assert_eq!(ports(task.find(&Id::UNKNOWN, &from)), vec![1, 3]);
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn find_ignores_unroutable_requesters() {
+        let token = tokio_util::sync::CancellationToken::new();
+        let peers = Peers::new(token.clone(), crate::Limits::default());
+        let (node, mut task) = node(id(200), &peers);
+        let addrs = ["[::1]:1", "[fe80::1]:2", "[fd00::1]:3"].map(|a| a.parse::<SocketAddrV6>().unwrap());
+        for (n, addr) in (1..).zip(addrs) {
+            let link = peers.connect(&id(n), &node, &addr).unwrap();
+            task.table.entry(node.id().similarity(&id(n))).or_default().insert(addr, link);
+        }
+        for from in ["[::1]:100", "[fe80::2]:100"] {
+            assert!(task.find(&Id::UNKNOWN, &Info::new(id(100), from.parse().unwrap())).is_empty());
+        }
+        assert_eq!(ports(task.find(&Id::UNKNOWN, &Info::new(id(100), "[fd00::2]:100".parse().unwrap()))), vec![3]);
+        token.cancel();
+    }
+
+    /// Create a node with the given [Id] and an unspawned task for it (see [test::node])
+    fn node(id: Id, peers: &Peers) -> (Arc<Node>, Task) {
+        let node = test::node(id, peers);
+        let task = node.unspawned_task(peers.clone());
+        (node, task)
+    }
+
+    #[tokio::test]
+    async fn migrate_keeps_old_entry_until_new_link_is_established() {
+        let token = tokio_util::sync::CancellationToken::new();
+        let peers = Peers::new(token.clone(), crate::Limits::default());
+        let (remote, _) = node(id(2), &Peers::new(token.clone(), crate::Limits::default()));
+        let (node, mut task) = node(id(200), &peers);
+        let old = peers.connect(&id(1), &node, remote.addr()).unwrap();
+        let index = node.id().similarity(&id(1));
+        task.table.entry(index).or_default().insert(*remote.addr(), old.clone());
+        task.stat.send_modify(|s| s.table += 1);
+        task.migrate(id(1), Info::new(id(2), *remote.addr()));
+        task.migrate(id(1), Info::new(id(2), *remote.addr()));
+        assert_eq!(task.migrations.len(), 1);
+        assert!(old.token().is_cancelled());
+        assert!(Arc::ptr_eq(&task.table[&index][remote.addr()], &old));
+        let (old, new) = task.migrations.join_next().await.unwrap().unwrap();
+        assert!(new.stat().borrow().status.is_good());
+        task.migrated(old, new.clone());
+        let index = node.id().similarity(&id(2));
+        assert!(Arc::ptr_eq(&task.table[&index][remote.addr()], &new));
+        assert_eq!(new.peer().previous_id(), Some(id(1)));
+        assert_eq!((task.count(), task.stat.borrow().migrated), (1, 1));
+        assert!(task.migrating.is_empty());
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn counts_seed_responses_from_linked_seeds() {
+        let token = tokio_util::sync::CancellationToken::new();
+        let peers = Peers::new(token.clone(), crate::Limits::default());
+        let (node, mut task) = node(id(200), &peers);
+        let seed = SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, 1, 0, 0);
+        let _link = peers.connect(&id(1), &node, &seed).unwrap();
+        let t = task.seed_queries.start(seed);
+        let response = |t| Msg::find_node_response(t, &[], &id(1), &[]).encode();
+        assert_eq!(task.route(&seed, &response(&[0; 8])), Some(true));
+        assert_eq!(task.route(&seed, &response(&t)), None);
+        task.dispatch(seed, &response(&t), &mut vec![]).await;
+        assert_eq!(task.stat.borrow().responded, 1);
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn migrate_ignores_nodes_not_in_table() {
+        let token = tokio_util::sync::CancellationToken::new();
+        let peers = Peers::new(token.clone(), crate::Limits::default());
+        let (node, mut task) = node(id(200), &peers);
+        let remote = SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, 1, 0, 0);
+        let old = peers.connect(&id(1), &node, &remote).unwrap();
+        task.migrate(id(1), Info::new(id(2), remote));
+        assert!(task.migrations.is_empty() && !old.token().is_cancelled());
+        assert!(!peers.borrow().contains_key(&id(2)));
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn remove_keeps_entry_taken_over_by_another_link() {
+        let token = tokio_util::sync::CancellationToken::new();
+        let peers = Peers::new(token.clone(), crate::Limits::default());
+        let ((node, mut task), (other, _)) = (node(id(200), &peers), node(id(200), &peers));
+        let remote = SocketAddrV6::new(std::net::Ipv6Addr::LOCALHOST, 1, 0, 0);
+        let a = peers.connect(&id(1), &node, &remote).unwrap();
+        let b = peers.connect(&id(1), &other, &remote).unwrap();
+        let index = node.id().similarity(&id(1));
+        task.table.entry(index).or_default().insert(remote, b.clone());
+        task.stat.send_modify(|s| s.table += 1);
+        task.remove(a);
+        assert!(Arc::ptr_eq(&task.table[&index][&remote], &b) && !b.token().is_cancelled());
+        task.remove(b.clone());
+        assert!(task.table[&index].is_empty() && b.token().is_cancelled());
+        assert_eq!(task.stat.borrow().table, 0);
+        token.cancel();
+    }
+}
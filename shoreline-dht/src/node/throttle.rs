@@ -0,0 +1,199 @@
+use crate::constants::*;
+use std::collections::BTreeMap;
+use std::net::Ipv6Addr;
+use tokio::time::Instant;
+
+/// Decision on a datagram from an unsolicited source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// The source exceeded its rate
+    Throttle,
+    /// The source is temporarily banned
+    Ban,
+}
+
+/// Rate limits for datagrams on the node socket
+///
+/// Each source address and each /64 prefix has a token bucket. A source
+/// exceeding its rate [THROTTLE_BAN_STRIKES] times is banned for
+/// [THROTTLE_BAN_DURATION]; the same applies to prefixes. Lookups answered by
+/// the node task share another bucket (see [Self::lookup]).
+#[derive(Debug)]
+pub struct Throttle {
+    addrs: Buckets<Ipv6Addr>,
+    prefixes: Buckets<u64>,
+    lookups: Option<(f32, Instant)>,
+}
+
+impl Throttle {
+    /// Check whether an unsolicited lookup may be answered
+    ///
+    /// All sources together get [THROTTLE_LOOKUP_RATE] lookups per second (with
+    /// bursts of [THROTTLE_LOOKUP_BURST]). Excess lookups are to be shed.
+    pub fn lookup(&mut self, now: Instant) -> bool {
+        let (tokens, last) = self.lookups.get_or_insert((THROTTLE_LOOKUP_BURST, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f32() * THROTTLE_LOOKUP_RATE).min(THROTTLE_LOOKUP_BURST);
+        *last = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+
+    pub fn check(&mut self, ip: &Ipv6Addr, now: Instant) -> Verdict {
+        let prefix = (ip.to_bits() >> 64) as u64;
+        match self.prefixes.check(prefix, now) {
+            Verdict::Allow => self.addrs.check(*ip, now),
+            verdict => verdict,
+        }
+    }
+
+    /// Forget sources that are neither banned nor have been active recently
+    pub fn prune(&mut self, now: Instant) {
+        self.addrs.prune(now);
+        self.prefixes.prune(now);
+    }
+
+    /// Get the number of banned addresses and prefixes
+    pub fn bans(&self, now: Instant) -> usize {
+        self.addrs.bans(now) + self.prefixes.bans(now)
+    }
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            addrs: Buckets::new(THROTTLE_ADDR_RATE, THROTTLE_ADDR_BURST),
+            prefixes: Buckets::new(THROTTLE_PREFIX_RATE, THROTTLE_PREFIX_BURST),
+            lookups: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f32,
+    last: Instant,
+    strikes: u32,
+    banned: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Buckets<K> {
+    map: BTreeMap<K, Bucket>,
+    rate: f32,
+    burst: f32,
+}
+
+impl<K: Ord> Buckets<K> {
+    fn new(rate: f32, burst: f32) -> Self {
+        Self { map: BTreeMap::new(), rate, burst }
+    }
+
+    fn check(&mut self, key: K, now: Instant) -> Verdict {
+        if !self.map.contains_key(&key) && self.map.len() >= THROTTLE_ENTRIES_MAX {
+            self.prune(now);
+            if self.map.len() >= THROTTLE_ENTRIES_MAX {
+                return Verdict::Throttle;
+            }
+        }
+        let burst = self.burst;
+        let b = self.map.entry(key).or_insert(Bucket { tokens: burst, last: now, strikes: 0, banned: None });
+        if let Some(until) = b.banned {
+            if now < until {
+                return Verdict::Ban;
+            }
+            b.banned = None;
+        }
+        b.tokens = (b.tokens + now.duration_since(b.last).as_secs_f32() * self.rate).min(burst);
+        b.last = now;
+        if b.tokens >= 1.0 {
+            b.tokens -= 1.0;
+            return Verdict::Allow;
+        }
+        b.strikes += 1;
+        if b.strikes >= THROTTLE_BAN_STRIKES {
+            b.strikes = 0;
+            b.banned = Some(now + THROTTLE_BAN_DURATION);
+        }
+        Verdict::Throttle
+    }
+
+    /// Remove buckets that have refilled completely (strikes are forgiven)
+    fn prune(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.map.retain(|_, b| {
+            let tokens = b.tokens + now.duration_since(b.last).as_secs_f32() * rate;
+            b.banned.is_some_and(|until| now < until) || tokens < burst
+        });
+    }
+
+    fn bans(&self, now: Instant) -> usize {
+        self.map.values().filter(|b| b.banned.is_some_and(|until| now < until)).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Duration;
+
+    const A: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1);
+    const B: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 2);
+    const C: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 2, 0, 0, 0, 1);
+
+    fn burst(t: &mut Throttle, ip: &Ipv6Addr, n: usize, now: Instant) -> Vec<Verdict> {
+        (0..n).map(|_| t.check(ip, now)).collect()
+    }
+
+    #[test]
+    fn throttles_address_after_burst() {
+        let mut t = Throttle::default();
+        let now = Instant::now();
+        let n = THROTTLE_ADDR_BURST as usize;
+        assert!(burst(&mut t, &A, n, now).iter().all(|v| *v == Verdict::Allow));
+        assert_eq!(t.check(&A, now), Verdict::Throttle);
+        assert_eq!(t.check(&C, now), Verdict::Allow);
+        let later = now + Duration::from_secs_f32(1.0 / THROTTLE_ADDR_RATE);
+        assert_eq!(t.check(&A, later), Verdict::Allow);
+    }
+
+    #[test]
+    fn throttles_prefix_across_addresses() {
+        let mut t = Throttle::default();
+        let now = Instant::now();
+        let n = THROTTLE_PREFIX_BURST as usize;
+        for i in 0..n {
+            let ip = Ipv6Addr::from_bits(A.to_bits() + i as u128);
+            assert_eq!(t.check(&ip, now), Verdict::Allow);
+        }
+        assert_eq!(t.check(&B, now + Duration::from_millis(1)), Verdict::Throttle);
+        assert_eq!(t.check(&C, now), Verdict::Allow);
+    }
+
+    #[test]
+    fn bans_repeat_offender() {
+        let mut t = Throttle::default();
+        let now = Instant::now();
+        let n = THROTTLE_ADDR_BURST as usize + THROTTLE_BAN_STRIKES as usize;
+        assert_eq!(burst(&mut t, &A, n, now).last(), Some(&Verdict::Throttle));
+        assert_eq!(t.check(&A, now + Duration::from_secs(60)), Verdict::Ban);
+        assert_eq!(t.bans(now), 1);
+        t.prune(now + THROTTLE_BAN_DURATION);
+        assert_eq!(t.check(&A, now + THROTTLE_BAN_DURATION), Verdict::Allow);
+        assert_eq!(t.bans(now + THROTTLE_BAN_DURATION), 0);
+    }
+
+    #[test]
+    fn sheds_lookups_beyond_node_rate() {
+        let mut t = Throttle::default();
+        let now = Instant::now();
+        let n = THROTTLE_LOOKUP_BURST as usize;
+        assert!((0..n).all(|_| t.lookup(now)));
+        assert!(!t.lookup(now));
+        assert_eq!(t.check(&A, now), Verdict::Allow);
+        assert!(t.lookup(now + Duration::from_secs_f32(1.0 / THROTTLE_LOOKUP_RATE)));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::constants::*;
+use std::collections::BTreeMap;
+use std::net::SocketAddrV6;
+use tokio::time::Instant;
+
+/// Votes of remote nodes on our external address (as reported in the `ip` key)
+///
+/// Votes are keyed by the voter's /64 prefix rather than its self-reported ID,
+/// so a single host cannot outvote the others by inventing IDs. Each prefix has
+/// a single vote (the latest one counts). Only the most recent
+/// [EXTERNAL_VOTES_MAX] prefixes are retained.
+#[derive(Debug, Default)]
+pub struct Votes {
+    votes: BTreeMap<u64, (SocketAddrV6, Instant)>,
+}
+
+impl Votes {
+    pub fn vote(&mut self, voter: &SocketAddrV6, addr: SocketAddrV6, now: Instant) {
+        let prefix = (voter.ip().to_bits() >> 64) as u64;
+        self.votes.insert(prefix, (addr, now));
+        if self.votes.len() > EXTERNAL_VOTES_MAX
+            && let Some(prefix) = self.votes.iter().min_by_key(|(_, (_, t))| *t).map(|(p, _)| *p)
+        {
+            self.votes.remove(&prefix);
+        }
+    }
+
+    /// Get the address with the most votes (if it has at least [EXTERNAL_VOTES_MIN])
+    pub fn winner(&self) -> Option<SocketAddrV6> {
+        let mut tally = BTreeMap::<SocketAddrV6, usize>::new();
+        for (addr, _) in self.votes.values() {
+            *tally.entry(*addr).or_default() += 1;
+        }
+        let (addr, n) = tally.into_iter().max_by_key(|(_, n)| *n)?;
+        (n >= EXTERNAL_VOTES_MIN).then_some(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+    use tokio::time::Duration;
+
+    fn addr(s: &str) -> SocketAddrV6 {
+        s.parse().unwrap()
+    }
+
+    /// A voter in the `n`th /64 prefix
+    fn voter(n: u16) -> SocketAddrV6 {
+        SocketAddrV6::new(std::net::Ipv6Addr::new(0x2001, 0xdb8, 0, n, 0, 0, 0, 1), 6881, 0, 0)
+    }
+
+    #[test]
+    fn requires_minimum_votes() {
+        let mut votes = Votes::default();
+        let now = Instant::now();
+        let ext = addr("[2001:db8:ffff::1]:6881");
+        for n in 0..EXTERNAL_VOTES_MIN as u16 - 1 {
+            votes.vote(&voter(n), ext, now);
+        }
+        assert_eq!(votes.winner(), None);
+        votes.vote(&voter(100), ext, now);
+        assert_eq!(votes.winner(), Some(ext));
+    }
+
+    #[test]
+    fn counts_one_vote_per_prefix() {
+        let mut votes = Votes::default();
+        let now = Instant::now();
+        let (ext, fake) = (addr("[2001:db8:ffff::1]:6881"), addr("[2001:db8:eeee::1]:6881"));
+        for n in 0..EXTERNAL_VOTES_MAX as u16 {
+            let ip = Ipv6Addr::from_bits(voter(0).ip().to_bits() + n as u128);
+            votes.vote(&SocketAddrV6::new(ip, 6881, 0, 0), fake, now);
+        }
+        assert_eq!(votes.winner(), None);
+        for n in 1..=EXTERNAL_VOTES_MIN as u16 {
+            votes.vote(&voter(n), ext, now);
+        }
+        assert_eq!(votes.winner(), Some(ext));
+    }
+
+    #[test]
+    fn evicts_oldest_voter() {
+        let mut votes = Votes::default();
+        let now = Instant::now();
+        let (old, new) = (addr("[2001:db8:ffff::1]:6881"), addr("[2001:db8:eeee::1]:6881"));
+        for n in 0..EXTERNAL_VOTES_MAX as u16 {
+            votes.vote(&voter(n), old, now);
+        }
+        let later = now + Duration::from_secs(1);
+        for n in 0..EXTERNAL_VOTES_MAX as u16 {
+            votes.vote(&voter(1000 + n), new, later);
+            assert_eq!(votes.votes.len(), EXTERNAL_VOTES_MAX);
+        }
+        assert!(votes.votes.values().all(|(addr, _)| *addr == new));
+        assert_eq!(votes.winner(), Some(new));
+    }
+}
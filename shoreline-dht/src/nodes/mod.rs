@@ -1,21 +1,40 @@
-use crate::net::Netwatch;
-use crate::{Id, Node, Peers};
+use crate::bootstrap::Bootstrap;
+use crate::constants::*;
+use crate::io::SocketOptions;
+use crate::net::{Netwatch, Policy};
+use crate::pcp::{self, PortMapping};
+use crate::{Id, Info, Node, Peers, Status};
 use std::collections::BTreeMap;
 use std::net::SocketAddrV6;
 use std::ops::Deref;
 use std::sync::Arc;
 use tokio::select;
 use tokio::sync::watch;
+use tokio::time::{interval, timeout};
 
+/// The [Node]s of the DHT, one per interface address (see [Policy])
+///
+/// Each node is a separate DHT node with its own routing table and an [Id]
+/// derived from the host [Id] and its address (see [Id::for_addr]), as BEP 45
+/// recommends. Nodes advertise each other in their responses, while each
+/// [Peer](crate::Peer) aggregates its links across nodes.
 #[derive(Debug, Clone)]
 pub struct Nodes {
     nodes: watch::Sender<BTreeMap<String, Arc<Node>>>,
 }
 
 impl Nodes {
-    pub fn new(id: Id, port: u16, peers: Peers, seeds: watch::Receiver<Vec<SocketAddrV6>>) -> Self {
+    pub fn new(
+        id: Id,
+        port: u16,
+        policy: Policy,
+        mapping: PortMapping,
+        opts: SocketOptions,
+        peers: Peers,
+        boot: Bootstrap,
+    ) -> Self {
         let nodes = watch::channel(BTreeMap::new()).0;
-        tokio::spawn(Self::run(id, port, peers.clone(), nodes.clone(), seeds));
+        tokio::spawn(Self::run(id, port, policy, mapping, opts, peers.clone(), nodes.clone(), boot));
         Self { nodes }
     }
 
@@ -23,15 +42,20 @@ impl Nodes {
         self.nodes.borrow()
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn run(
         id: Id,
         port: u16,
+        policy: Policy,
+        mapping: PortMapping,
+        opts: SocketOptions,
         peers: Peers,
         nodes: watch::Sender<BTreeMap<String, Arc<Node>>>,
-        seeds: watch::Receiver<Vec<SocketAddrV6>>,
+        boot: Bootstrap,
     ) {
         let token = peers.ctok().clone();
-        let mut netwatch = Netwatch::new();
+        let mut netwatch = Netwatch::new(policy);
+        let siblings = nodes.subscribe();
         loop {
             select! {
                 _ = token.cancelled() => {
@@ -41,24 +65,97 @@ impl Nodes {
                 }
                 _ = netwatch.changed() => {
                     let desired = netwatch.list();
+                    let mut migrations = vec![];
                     nodes.send_modify(|m| {
+                        let mut changed = BTreeMap::new();
                         for (k,v) in std::mem::take(m).into_iter() {
                             match desired.get(&k) {
                                 Some(addr) if addr == v.addr().ip() => { m.insert(k, v); },
-                                _ => v.token().cancel(),
+                                Some(_) => { changed.insert(k, v); },
+                                None => v.token().cancel(),
                             };
                         };
                         for (interface, addr) in desired {
                             if !m.contains_key(&interface) {
+                                let node_id = id.for_addr(&addr);
                                 let addr = SocketAddrV6::new(addr, port, 0, 0);
-                                if let Ok(node) = Node::new(id, interface.clone(), addr, peers.clone(), seeds.clone()) {
+                                if let Ok(node) = Node::new(node_id, interface.clone(), addr, peers.clone(), boot.clone(), siblings.clone(), &opts) {
+                                    pcp::spawn(node.clone(), mapping.clone());
+                                    if let Some(old) = changed.remove(&interface) {
+                                        migrations.push((old, node.clone()));
+                                    }
                                     m.insert(interface, node);
                                 }
                             }
                         }
+                        changed.into_values().for_each(|n| n.token().cancel());
                     });
+                    for (old, new) in migrations {
+                        tokio::spawn(Self::migrate(peers.clone(), old, new));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hand the links of `old` over to `new` and terminate `old`
+    ///
+    /// This is used when the address of an interface changes (e.g. privacy
+    /// address rotation or renumbering) so that the routing table is not lost.
+    /// The new node has a different [Id] (see [Id::for_addr]), which the peers
+    /// learn from its queries. The links are re-established from the new address while `old` keeps
+    /// serving its links until all new ones passed [Status::Init] (at most
+    /// [NODE_MIGRATION_GRACE]).
+    async fn migrate(peers: Peers, old: Arc<Node>, new: Arc<Node>) {
+        let mut infos = vec![];
+        for peer in peers.borrow().values() {
+            for ((local, remote), link) in peer.links().iter() {
+                if local == old.addr() && !link.stat().borrow().status.is_expendable() {
+                    infos.push(Info::new(*peer.id(), *remote));
                 }
             }
         }
+        log::info!("Migrating {} links from {} to {}", infos.len(), old.addr(), new.addr());
+        for info in &infos {
+            let _ = new.suggest(info);
+        }
+        let established = |info: &Info| {
+            let peer = peers.borrow().get(&info.id).cloned();
+            let link = peer.and_then(|p| p.links().get(&(*new.addr(), info.addr)).cloned());
+            link.is_some_and(|l| l.stat().borrow().status != Status::Init)
+        };
+        let _ = timeout(NODE_MIGRATION_GRACE, async {
+            let mut intvl = interval(NODE_MIGRATION_CHECK_INTERVAL);
+            while !infos.iter().all(established) {
+                intvl.tick().await;
+            }
+        })
+        .await;
+        old.token().cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Limits;
+    use crate::util::test::node;
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn migrate_keeps_old_node_until_new_links_are_established() {
+        let token = CancellationToken::new();
+        let peers = Peers::new(token.child_token(), Limits::default());
+        let remote = node(Id::random(), &Peers::new(token.child_token(), Limits::default()));
+        let id = Id::random();
+        let (old, new) = (node(id, &peers), node(id, &peers));
+        let link = peers.connect(remote.id(), &old, remote.addr()).unwrap();
+        assert!(link.init().await.unwrap().is_good());
+        Nodes::migrate(peers.clone(), old.clone(), new.clone()).await;
+        assert!(old.token().is_cancelled());
+        let peer = peers.borrow().get(remote.id()).cloned().unwrap();
+        let migrated = peer.links().get(&(*new.addr(), *remote.addr())).cloned().unwrap();
+        assert!(migrated.stat().borrow().status.is_good());
+        token.cancel();
     }
 }
@@ -0,0 +1,342 @@
+use crate::Node;
+use crate::constants::*;
+use std::fmt::Display;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::time::{Duration, sleep, timeout};
+
+const VERSION: u8 = 2;
+const VERSION_NAT_PMP: u8 = 0;
+const NAT_PMP_RESPONSE_LEN: usize = 8;
+const OPCODE_MAP: u8 = 1;
+const RESPONSE: u8 = 0x80;
+const PROTOCOL_UDP: u8 = 17;
+const REQUEST_LEN: usize = 60;
+const NONCE_LEN: usize = 12;
+
+/// Port mapping settings (PCP, RFC 6887)
+///
+/// When enabled, each [Node] requests a mapping (or IPv6 firewall pinhole) for
+/// its address from the gateway and renews it periodically.
+#[derive(Debug, Clone, Default)]
+pub struct PortMapping {
+    pub enabled: bool,
+    /// The PCP server; the interface's default router if absent (Linux only)
+    pub gateway: Option<Ipv6Addr>,
+}
+
+/// Port mapping state of a [Node]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mapping {
+    /// Port mapping is disabled
+    #[default]
+    Disabled,
+    /// No gateway configured or found
+    NoGateway,
+    /// Waiting for the first response
+    Requesting,
+    /// The gateway mapped `external` to the node for `lifetime`
+    Mapped { external: SocketAddrV6, lifetime: Duration },
+    /// The gateway refused with the given PCP result code
+    Refused(u8),
+    /// The gateway only speaks NAT-PMP (which is IPv4 only)
+    Unsupported,
+    /// The gateway did not respond
+    Unreachable,
+}
+
+impl Display for Mapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "Disabled"),
+            Self::NoGateway => write!(f, "No gateway"),
+            Self::Requesting => write!(f, "Requesting"),
+            Self::Mapped { external, lifetime } => write!(f, "Mapped to {} for {}s", external, lifetime.as_secs()),
+            Self::Refused(code) => write!(f, "Refused: {}", result_str(*code)),
+            Self::Unsupported => write!(f, "Gateway only supports NAT-PMP"),
+            Self::Unreachable => write!(f, "Gateway unreachable"),
+        }
+    }
+}
+
+/// Maintain the port mapping for `node` until it is terminated
+pub fn spawn(node: Arc<Node>, config: PortMapping) {
+    if config.enabled {
+        tokio::spawn(run(node, config));
+    }
+}
+
+async fn run(node: Arc<Node>, config: PortMapping) {
+    let index = || pnet_datalink::interfaces().into_iter().find(|i| i.name == node.name()).map(|i| i.index);
+    let gateway = config.gateway.or_else(|| default_router(node.name()));
+    let Some(gateway) = gateway else {
+        node.set_mapping(Mapping::NoGateway);
+        return;
+    };
+    let scope_id = if gateway.is_unicast_link_local() {
+        index().unwrap_or(0)
+    } else {
+        0
+    };
+    let gateway = SocketAddrV6::new(gateway, PCP_SERVER_PORT, 0, scope_id);
+    let nonce = rand::random::<[u8; NONCE_LEN]>();
+    let lifetime = PCP_LIFETIME.as_secs() as u32;
+
+    node.set_mapping(Mapping::Requesting);
+    loop {
+        let mapping = tokio::select! {
+            m = request_for(&node, &gateway, &nonce, lifetime) => m,
+            _ = node.token().cancelled() => break,
+        };
+        node.set_mapping(mapping);
+        let renew = match mapping {
+            Mapping::Mapped { lifetime, .. } => (lifetime / 2).max(PCP_RENEW_MIN),
+            _ => PCP_RETRY_INTERVAL,
+        };
+        tokio::select! {
+            _ = sleep(renew) => {}
+            _ = node.token().cancelled() => break,
+        }
+    }
+
+    // Delete the mapping (best effort)
+    let _ = timeout(PCP_TIMEOUT, request_for(&node, &gateway, &nonce, 0)).await;
+}
+
+/// Request a mapping for `node` from one of its temporary sockets (see [request])
+async fn request_for(node: &Node, gateway: &SocketAddrV6, nonce: &[u8; NONCE_LEN], lifetime: u32) -> Mapping {
+    match node.temp_socket() {
+        Ok(sock) => request(&sock, gateway, node.addr(), nonce, lifetime).await,
+        Err(_) => Mapping::Unreachable,
+    }
+}
+
+/// Request a mapping for `client` from `sock` with the given lifetime (in seconds)
+///
+/// The request is retransmitted [PCP_ATTEMPTS] times with doubling timeout.
+/// Datagrams from other sources than `gateway` as well as responses with a
+/// different nonce or for another port are ignored.
+async fn request(
+    sock: &UdpSocket,
+    gateway: &SocketAddrV6,
+    client: &SocketAddrV6,
+    nonce: &[u8; NONCE_LEN],
+    lifetime: u32,
+) -> Mapping {
+    let req = encode_request(client, nonce, lifetime);
+    let mut rbuf = [0u8; 1100];
+    let mut wait = PCP_TIMEOUT;
+    for _ in 0..PCP_ATTEMPTS {
+        if sock.send_to(&req, gateway).await.is_err() {
+            return Mapping::Unreachable;
+        }
+        let recv = async {
+            loop {
+                if let Ok((len, SocketAddr::V6(from))) = sock.recv_from(&mut rbuf).await
+                    && from.ip() == gateway.ip()
+                    && from.port() == gateway.port()
+                    && let Some(mapping) = decode_response(&rbuf[..len], client, nonce)
+                {
+                    return mapping;
+                }
+            }
+        };
+        if let Ok(mapping) = timeout(wait, recv).await {
+            return mapping;
+        }
+        wait *= 2;
+    }
+    Mapping::Unreachable
+}
+
+fn encode_request(client: &SocketAddrV6, nonce: &[u8; NONCE_LEN], lifetime: u32) -> [u8; REQUEST_LEN] {
+    let mut buf = [0u8; REQUEST_LEN];
+    buf[0] = VERSION;
+    buf[1] = OPCODE_MAP;
+    buf[4..8].copy_from_slice(&lifetime.to_be_bytes());
+    buf[8..24].copy_from_slice(&client.ip().octets());
+    buf[24..36].copy_from_slice(nonce);
+    buf[36] = PROTOCOL_UDP;
+    buf[40..42].copy_from_slice(&client.port().to_be_bytes());
+    // Suggest the same external port and address (IPv6 pinhole)
+    buf[42..44].copy_from_slice(&client.port().to_be_bytes());
+    buf[44..60].copy_from_slice(&client.ip().octets());
+    buf
+}
+
+/// Decode a MAP response (or [None] if it is not a response to our request)
+///
+/// The nonce, protocol and port are checked before the result code, so that
+/// only the answer to our request can refuse the mapping. A NAT-PMP gateway
+/// answers with its own (shorter) error response, which carries no nonce.
+fn decode_response(buf: &[u8], client: &SocketAddrV6, nonce: &[u8; NONCE_LEN]) -> Option<Mapping> {
+    if buf.len() >= NAT_PMP_RESPONSE_LEN && buf[0] == VERSION_NAT_PMP && buf[1] & RESPONSE != 0 {
+        return Some(Mapping::Unsupported);
+    }
+    if buf.len() < REQUEST_LEN || buf[0] != VERSION || buf[1] != RESPONSE | OPCODE_MAP {
+        return None;
+    }
+    if &buf[24..36] != nonce || buf[36] != PROTOCOL_UDP || u16::from_be_bytes([buf[40], buf[41]]) != client.port() {
+        return None;
+    }
+    let result = buf[3];
+    if result != 0 {
+        return Some(Mapping::Refused(result));
+    }
+    let lifetime = u32::from_be_bytes(buf[4..8].try_into().ok()?);
+    let port = u16::from_be_bytes([buf[42], buf[43]]);
+    let ip: [u8; 16] = buf[44..60].try_into().ok()?;
+    let external = SocketAddrV6::new(Ipv6Addr::from(ip), port, 0, 0);
+    Some(Mapping::Mapped { external, lifetime: Duration::from_secs(lifetime as u64) })
+}
+
+fn result_str(code: u8) -> &'static str {
+    match code {
+        1 => "Unsupported version",
+        2 => "Not authorized",
+        3 => "Malformed request",
+        4 => "Unsupported opcode",
+        5 => "Unsupported option",
+        6 => "Malformed option",
+        7 => "Network failure",
+        8 => "No resources",
+        9 => "Unsupported protocol",
+        10 => "User exceeded quota",
+        11 => "Cannot provide external",
+        12 => "Address mismatch",
+        13 => "Excessive remote peers",
+        _ => "Unknown error",
+    }
+}
+
+/// Find the default router of the given interface
+#[cfg(target_os = "linux")]
+fn default_router(interface: &str) -> Option<Ipv6Addr> {
+    // Columns: dst, dst prefix len, src, src prefix len, next hop, metric, refcnt, use, flags, device
+    let routes = std::fs::read_to_string("/proc/net/ipv6_route").ok()?;
+    routes.lines().find_map(|line| {
+        let cols = line.split_whitespace().collect::<Vec<_>>();
+        let default = cols.len() == 10 && cols[0].bytes().all(|b| b == b'0') && cols[1] == "00";
+        let hop = u128::from_str_radix(cols.get(4)?, 16).ok()?;
+        Some(Ipv6Addr::from(hop)).filter(|ip| default && cols[9] == interface && !ip.is_unspecified())
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_router(_interface: &str) -> Option<Ipv6Addr> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::socket_bound;
+
+    const CLIENT: SocketAddrV6 = SocketAddrV6::new(Ipv6Addr::LOCALHOST, 6881, 0, 0);
+    const NONCE: [u8; NONCE_LEN] = *b"shoreline-12";
+
+    /// A socket for requests from [CLIENT]
+    fn client() -> UdpSocket {
+        socket_bound(SocketAddrV6::new(*CLIENT.ip(), 0, 0, 0)).unwrap()
+    }
+
+    /// A gateway answering each request with the replies returned by `f`
+    async fn fake_gateway<F>(f: F) -> SocketAddrV6
+    where
+        F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + 'static,
+    {
+        let sock = UdpSocket::bind("[::1]:0").await.unwrap();
+        let SocketAddr::V6(addr) = sock.local_addr().unwrap() else {
+            unreachable!()
+        };
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1100];
+            while let Ok((len, from)) = sock.recv_from(&mut buf).await {
+                assert_eq!(len, REQUEST_LEN);
+                assert_eq!(&buf[..2], &[VERSION, OPCODE_MAP]);
+                for reply in f(&buf[..len]) {
+                    sock.send_to(&reply, from).await.unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    fn response(req: &[u8], result: u8, lifetime: u32, external: SocketAddrV6) -> Vec<u8> {
+        let mut buf = req.to_vec();
+        buf[1] = RESPONSE | OPCODE_MAP;
+        buf[3] = result;
+        buf[4..8].copy_from_slice(&lifetime.to_be_bytes());
+        buf[8..24].fill(0);
+        buf[42..44].copy_from_slice(&external.port().to_be_bytes());
+        buf[44..60].copy_from_slice(&external.ip().octets());
+        buf
+    }
+
+    #[tokio::test]
+    async fn maps_and_reports_lifetime() {
+        let external: SocketAddrV6 = "[2001:db8::1]:16881".parse().unwrap();
+        let gateway = fake_gateway(move |req| {
+            let mut other = response(req, 0, 60, external);
+            other[24] ^= 0xff;
+            vec![other, response(req, 0, 600, external)]
+        })
+        .await;
+        let mapping = request(&client(), &gateway, &CLIENT, &NONCE, 7200).await;
+        assert_eq!(mapping, Mapping::Mapped { external, lifetime: Duration::from_secs(600) });
+    }
+
+    #[tokio::test]
+    async fn reports_result_code() {
+        let gateway = fake_gateway(|req| vec![response(req, 2, 0, CLIENT)]).await;
+        let mapping = request(&client(), &gateway, &CLIENT, &NONCE, 7200).await;
+        assert_eq!(mapping, Mapping::Refused(2));
+        assert_eq!(mapping.to_string(), "Refused: Not authorized");
+    }
+
+    #[tokio::test]
+    async fn detects_nat_pmp_gateway() {
+        let gateway = fake_gateway(|_| vec![vec![VERSION_NAT_PMP, RESPONSE, 0, 1, 0, 0, 0, 0]]).await;
+        assert_eq!(request(&client(), &gateway, &CLIENT, &NONCE, 7200).await, Mapping::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn ignores_refusal_for_other_nonce() {
+        let external: SocketAddrV6 = "[2001:db8::1]:16881".parse().unwrap();
+        let gateway = fake_gateway(move |req| {
+            let mut spoofed = response(req, 2, 0, CLIENT);
+            spoofed[24] ^= 0xff;
+            vec![spoofed, vec![VERSION_NAT_PMP], response(req, 0, 600, external)]
+        })
+        .await;
+        let mapping = request(&client(), &gateway, &CLIENT, &NONCE, 7200).await;
+        assert_eq!(mapping, Mapping::Mapped { external, lifetime: Duration::from_secs(600) });
+    }
+
+    #[tokio::test]
+    async fn ignores_other_sources() {
+        let external: SocketAddrV6 = "[2001:db8::1]:16881".parse().unwrap();
+        let sock = UdpSocket::bind("[::1]:0").await.unwrap();
+        let spoofer = UdpSocket::bind("[::1]:0").await.unwrap();
+        let SocketAddr::V6(gateway) = sock.local_addr().unwrap() else {
+            unreachable!()
+        };
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1100];
+            let (len, from) = sock.recv_from(&mut buf).await.unwrap();
+            let req = &buf[..len];
+            spoofer.send_to(&response(req, 2, 0, CLIENT), from).await.unwrap();
+            spoofer.send_to(&[VERSION_NAT_PMP, RESPONSE, 0, 1, 0, 0, 0, 0], from).await.unwrap();
+            sock.send_to(&response(req, 0, 600, external), from).await.unwrap();
+        });
+        let mapping = request(&client(), &gateway, &CLIENT, &NONCE, 7200).await;
+        assert_eq!(mapping, Mapping::Mapped { external, lifetime: Duration::from_secs(600) });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_silent_gateway() {
+        let gateway = fake_gateway(|_| vec![]).await;
+        assert_eq!(request(&client(), &gateway, &CLIENT, &NONCE, 7200).await, Mapping::Unreachable);
+    }
+}
@@ -1,74 +1,169 @@
+use crate::blocklist::Blocklists;
 use crate::constants::*;
-use crate::link::Link;
+use crate::common::Infos;
+use crate::error::Error;
+use crate::limits::Tasks;
+use crate::link::{Link, Stat, Status};
+use crate::net::Scope;
 use crate::{Id, Node};
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::net::SocketAddrV6;
 use std::ops;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use tokio::select;
 use tokio::sync::watch::{self};
-use tokio::time::sleep;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant, sleep};
 use tokio_util::sync::CancellationToken;
 
 #[derive(Debug)]
 pub struct Peer {
     id: Id,
     links: watch::Sender<BTreeMap<(SocketAddrV6, SocketAddrV6), Arc<Link>>>,
+    max_links: usize,
+    blocklists: watch::Receiver<Arc<Blocklists>>,
+    tasks: Tasks,
+    previous_id: Mutex<Option<Id>>,
     token: CancellationToken,
 }
 
 impl Peer {
-    pub fn new(id: Id, token: CancellationToken) -> Arc<Self> {
+    /// Create a peer with at most `max_links` links (see [Peers::get](crate::Peers::get))
+    pub(crate) fn new(
+        id: Id,
+        token: CancellationToken,
+        max_links: usize,
+        blocklists: watch::Receiver<Arc<Blocklists>>,
+        tasks: Tasks,
+    ) -> Arc<Self> {
         let links = watch::channel(BTreeMap::new()).0;
-        let links_ = links.clone();
-        let token_ = token.clone();
-        tokio::spawn(async move {
-            token_.cancelled().await;
-            links_.send_modify(BTreeMap::clear);
-        });
-        Arc::new(Self { id, links, token })
+        Arc::new(Self { id, links, max_links, blocklists, tasks, previous_id: Mutex::default(), token })
     }
 
     pub fn id(&self) -> &Id {
         &self.id
     }
 
+    /// Get the ID this peer last used on the same address before it changed (if any)
+    pub fn previous_id(&self) -> Option<Id> {
+        *self.previous_id.lock().unwrap()
+    }
+
+    pub(crate) fn set_previous_id(&self, id: Id) {
+        *self.previous_id.lock().unwrap() = Some(id);
+    }
+
+    /// Check whether the ID is used from unrelated networks (ID collision)
+    ///
+    /// Links are keyed by ID, so a single ID seen from different /48 prefixes of
+    /// the same [Scope] is merged into one peer although it most likely belongs
+    /// to several nodes (or a node impersonating another one).
+    pub fn is_collision(&self) -> bool {
+        is_collision(self.links.borrow().values().map(|l| l.addr()))
+    }
+
     pub fn is_empty(&self) -> bool {
         self.links.borrow().is_empty()
     }
 
-    pub fn connect(self: &Arc<Self>, node: &Arc<Node>, addr: &SocketAddrV6) -> Arc<Link> {
-        let mut conn = None;
-        let key = (node.addr().clone(), addr.clone());
+    /// Get the link from `node` to `addr` or create it
+    ///
+    /// A new link is removed [LINK_REMOVAL_DELAY] after it terminated, and the
+    /// peer is terminated along with its last link. Fails with
+    /// [Error::LimitExceeded] if the peer already has the maximum number of links
+    /// or [Limits::max_tasks](crate::Limits::max_tasks) is reached and with
+    /// [Error::Blocked] if `addr` is on a blocklist.
+    pub fn connect(self: &Arc<Self>, node: &Arc<Node>, addr: &SocketAddrV6) -> Result<Arc<Link>, Error> {
+        if self.blocklists.borrow().contains(addr.ip()) {
+            return Err(Error::Blocked);
+        }
+        let collision = self.is_collision();
+        let mut conn = Err(Error::LimitExceeded);
+        let key = (*node.addr(), *addr);
 
-        self.links.send_if_modified(|m| {
+        let created = self.links.send_if_modified(|m| {
             if let Some(c) = m.get(&key) {
-                conn = Some(c.clone());
+                conn = Ok(c.clone());
+                false
+            } else if m.len() >= self.max_links || self.tasks.is_full() {
                 false
             } else {
-                let c = Link::new(node.clone(), self.clone(), addr.clone());
-                conn = Some(c.clone());
+                let c = Link::new(node.clone(), self.clone(), *addr);
+                conn = Ok(c.clone());
                 m.insert(key, c);
                 true
             }
         });
 
-        let conn = conn.unwrap();
-        tokio::spawn({
-            let conn = conn.clone();
-            let peer = self.clone();
-            async move {
-                conn.token().cancelled().await;
-                sleep(LINK_REMOVAL_DELAY).await;
-                peer.links.send_modify(|m| m.remove(&key).map(drop).unwrap_or_default());
-                if peer.links.borrow().is_empty() {
-                    peer.token.cancel();
+        if created && !collision && self.is_collision() {
+            log::warn!("Peer {} seen from unrelated networks (ID collision)", self.id);
+        }
+
+        if let (true, Ok(conn)) = (created, &conn) {
+            self.tasks.spawn({
+                let conn = conn.clone();
+                let peer = self.clone();
+                async move {
+                    conn.token().cancelled().await;
+                    sleep(LINK_REMOVAL_DELAY).await;
+                    peer.links.send_modify(|m| m.remove(&key).map(drop).unwrap_or_default());
+                    if peer.links.borrow().is_empty() {
+                        peer.token.cancel();
+                    }
                 }
-            }
-        });
+            });
+        }
 
         conn
     }
 
+    /// Remove all links (when the peer is terminated)
+    pub(crate) fn clear(&self) {
+        self.links.send_modify(BTreeMap::clear);
+    }
+
+    /// Get the time a datagram was last received on any link ([None] without links)
+    pub fn last_active(&self) -> Option<Instant> {
+        self.links.borrow().values().map(|l| l.stat().borrow().rx_last).max()
+    }
+
+    /// Get the aggregated [Status] of all links (the best one)
+    pub fn status(&self) -> Status {
+        let links = self.links.borrow();
+        let status = links.values().map(|l| l.stat().borrow().status);
+        status.min_by_key(Status::rank).unwrap_or(Status::Term)
+    }
+
+    /// Get the link preferred for sending queries (if any)
+    pub fn best_link(&self) -> Option<Arc<Link>> {
+        self.links_by_preference().into_iter().next()
+    }
+
+    /// Get all links not terminated, ordered by status, loss and RTT (best first)
+    pub fn links_by_preference(&self) -> Vec<Arc<Link>> {
+        let mut links = self
+            .links
+            .borrow()
+            .values()
+            .map(|l| (l.stat().borrow().clone(), l.clone()))
+            .filter(|(stat, _)| stat.status != Status::Term)
+            .collect::<Vec<_>>();
+        links.sort_by(|a, b| preference(&a.0, &b.0));
+        links.into_iter().map(|(_, l)| l).collect()
+    }
+
+    /// Send a find_node query, racing the links in order of preference
+    ///
+    /// The next link is queried as soon as the previous ones failed or did not
+    /// answer within their SRTT. Fails with the last error or with
+    /// [Error::PeerUnreachable] if there is no usable link.
+    pub async fn find_node(&self, id: &Id) -> Result<Infos, Error> {
+        let id = *id;
+        let delay = |l: &Arc<Link>| l.stat().borrow().srtt.unwrap_or(RTO_MIN);
+        hedge(self.links_by_preference(), delay, move |l| async move { l.find_node(&id).await }).await
+    }
+
     pub fn links(&self) -> impl ops::Deref<Target = BTreeMap<(SocketAddrV6, SocketAddrV6), Arc<Link>>> + '_ {
         self.links.borrow()
     }
@@ -76,4 +171,169 @@ impl Peer {
     pub fn token(&self) -> &CancellationToken {
         &self.token
     }
+
+    /// Get the counter for the tasks of this peer and its links
+    pub(crate) fn tasks(&self) -> &Tasks {
+        &self.tasks
+    }
+}
+
+/// Order links by status, loss and SRTT (best first, links without RTT sample last)
+fn preference(a: &Stat, b: &Stat) -> Ordering {
+    let srtt = |s: &Stat| s.srtt.unwrap_or(Duration::MAX);
+    let rank = a.status.rank().cmp(&b.status.rank());
+    rank.then(a.loss().total_cmp(&b.loss())).then(srtt(a).cmp(&srtt(b)))
+}
+
+/// Run `query` on `links` one after another until one succeeds
+///
+/// A query on the next link is started when all running queries failed or when
+/// the last one started did not succeed within its `delay`. The running queries
+/// are aborted on success. Fails with the last error or [Error::PeerUnreachable]
+/// if there are no links.
+async fn hedge<L, T, F>(links: Vec<L>, delay: impl Fn(&L) -> Duration, query: impl Fn(L) -> F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: Future<Output = Result<T, Error>> + Send + 'static,
+{
+    let mut links = links.into_iter();
+    let mut queries = JoinSet::new();
+    let mut err = Error::PeerUnreachable;
+    loop {
+        let next = links.next().map(|l| {
+            let delay = delay(&l);
+            queries.spawn(query(l));
+            sleep(delay)
+        });
+        let more = next.is_some() && links.len() > 0;
+        let next = async {
+            match next {
+                Some(next) if more => next.await,
+                _ => std::future::pending().await,
+            }
+        };
+        tokio::pin!(next);
+        loop {
+            select! {
+                result = queries.join_next() => match result {
+                    Some(Ok(Ok(t))) => return Ok(t),
+                    Some(Ok(Err(e))) => err = e,
+                    Some(Err(_)) => (),
+                    None if more => break,
+                    None => return Err(err),
+                },
+                _ = &mut next => break,
+            }
+        }
+    }
+}
+
+/// Check whether any two routable addresses of the same [Scope] differ in their /48 prefix
+fn is_collision<'a>(addrs: impl Iterator<Item = &'a SocketAddrV6>) -> bool {
+    let mut nets = BTreeMap::new();
+    for addr in addrs.filter(|a| Scope::of(a.ip()).is_routable()) {
+        let net = addr.ip().to_bits() >> 80;
+        if *nets.entry(Scope::of(addr.ip())).or_insert(net) != net {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(s: &[&str]) -> Vec<SocketAddrV6> {
+        s.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn detects_collision_across_networks() {
+        let same = addrs(&["[2001:db8:1:2::1]:6881", "[2001:db8:1:3::1]:6882", "[fd00::1]:6881", "[fe80::1]:1"]);
+        assert!(!is_collision(same.iter()));
+        let unrelated = addrs(&["[2001:db8:1::1]:6881", "[2001:db8:2::1]:6881"]);
+        assert!(is_collision(unrelated.iter()));
+    }
+
+    #[test]
+    fn prefers_status_then_loss_then_rtt() {
+        let stat = |status, timeouts, srtt: Option<u64>| {
+            let mut stat = Stat::new();
+            (stat.status, stat.queries, stat.timeouts) = (status, 10, timeouts);
+            stat.srtt = srtt.map(Duration::from_millis);
+            stat
+        };
+        let mut stats = [
+            stat(Status::Init, 0, None),
+            stat(Status::Good, 0, None),
+            stat(Status::Good, 1, Some(10)),
+            stat(Status::Good, 0, Some(50)),
+            stat(Status::Fail, 0, Some(1)),
+            stat(Status::Questionable, 0, Some(1)),
+            stat(Status::Good, 0, Some(20)),
+        ];
+        stats.sort_by(preference);
+        let order = stats.iter().map(|s| (s.status, s.timeouts, s.srtt.map(|d| d.as_millis()))).collect::<Vec<_>>();
+        let expected = vec![
+            (Status::Good, 0, Some(20)),
+            (Status::Good, 0, Some(50)),
+            (Status::Good, 0, None),
+            (Status::Good, 1, Some(10)),
+            (Status::Questionable, 0, Some(1)),
+            (Status::Init, 0, None),
+            (Status::Fail, 0, Some(1)),
+        ];
+        assert_eq!(order, expected);
+    }
+
+    /// A query on link `(delay, answer after, result)`
+    type Query = (u64, u64, Result<u8, Error>);
+
+    async fn race(links: Vec<Query>) -> (Result<u8, Error>, Duration) {
+        let start = Instant::now();
+        let delay = |l: &Query| Duration::from_millis(l.0);
+        let result = hedge(links, delay, |(_, after, result)| async move {
+            sleep(Duration::from_millis(after)).await;
+            result
+        })
+        .await;
+        (result, start.elapsed())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn races_next_link_after_delay() {
+        let (result, elapsed) = race(vec![(100, 10_000, Ok(1)), (100, 50, Ok(2))]).await;
+        assert_eq!((result.unwrap(), elapsed), (2, Duration::from_millis(150)));
+        let (result, elapsed) = race(vec![(100, 120, Ok(1)), (100, 50, Ok(2))]).await;
+        assert_eq!((result.unwrap(), elapsed), (1, Duration::from_millis(120)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fails_over_immediately_on_error() {
+        let (result, elapsed) = race(vec![(100, 10, Err(Error::QueryTimeout)), (100, 10, Ok(2))]).await;
+        assert_eq!((result.unwrap(), elapsed), (2, Duration::from_millis(20)));
+        let failing = [Error::QueryTimeout, Error::LinkTerminated].map(|e| (100, 10, Err(e)));
+        let (result, _) = race(failing.into()).await;
+        assert!(matches!(result, Err(Error::LinkTerminated)));
+    }
+
+    #[tokio::test]
+    async fn fails_without_links() {
+        let blocklists = watch::channel(Arc::new(Blocklists::default())).1;
+        let peer = Peer::new(Id::random(), CancellationToken::new(), 4, blocklists, Tasks::new(usize::MAX));
+        assert!(matches!(peer.find_node(&Id::random()).await, Err(Error::PeerUnreachable)));
+        assert!(matches!(race(vec![]).await.0, Err(Error::PeerUnreachable)));
+    }
+
+    #[test]
+    fn tracks_last_previous_id() {
+        let blocklists = watch::channel(Arc::new(Blocklists::default())).1;
+        let peer = Peer::new(Id::random(), CancellationToken::new(), 4, blocklists, Tasks::new(usize::MAX));
+        let (a, b) = (Id::random(), Id::random());
+        assert_eq!(peer.previous_id(), None);
+        peer.set_previous_id(a);
+        peer.set_previous_id(b);
+        assert_eq!(peer.previous_id(), Some(b));
+    }
 }
@@ -1,5 +1,10 @@
-use crate::{Id, peer::Peer};
+use crate::blocklist::Blocklists;
+use crate::constants::*;
+use crate::error::Error;
+use crate::limits::{Limits, Tasks};
+use crate::{Id, Link, Node, peer::Peer};
 use std::collections::BTreeMap;
+use std::net::{Ipv6Addr, SocketAddrV6};
 use std::ops::Deref;
 use std::sync::Arc;
 use tokio::sync::watch;
@@ -9,10 +14,13 @@ use tokio_util::sync::CancellationToken;
 pub struct Peers {
     ctok: CancellationToken,
     peers: watch::Sender<BTreeMap<Id, Arc<Peer>>>,
+    limits: Limits,
+    tasks: Tasks,
+    blocklists: watch::Sender<Arc<Blocklists>>,
 }
 
 impl Peers {
-    pub fn new(ctok: CancellationToken) -> Self {
+    pub fn new(ctok: CancellationToken, limits: Limits) -> Self {
         let (peers, _) = watch::channel(BTreeMap::new());
         let ctok_ = ctok.clone();
         let peers_ = peers.clone();
@@ -20,28 +28,109 @@ impl Peers {
             ctok_.cancelled().await;
             peers_.send_modify(BTreeMap::clear);
         });
-        Self { ctok, peers }
+        let blocklists = watch::channel(Arc::default()).0;
+        let tasks = Tasks::new(limits.max_tasks);
+        Self { ctok, peers, limits, tasks, blocklists }
     }
 
-    pub fn get(&self, id: &Id) -> Arc<Peer> {
-        if let Some(peer) = self.peers.borrow().get(id) {
-            peer.clone()
-        } else {
-            let id = *id;
-            let ctok = self.ctok.child_token();
-            let peer = Peer::new(id, ctok.clone());
+    /// Get the [Peer] with the given [Id] or create it
+    ///
+    /// If [Limits::max_peers] is reached, the least recently active idle peer
+    /// is evicted. Fails with [Error::LimitExceeded] if there is none or if
+    /// [Limits::max_tasks] is reached. Lookup, eviction and insertion happen in
+    /// one update, so concurrent calls never create two peers with the same ID.
+    pub fn get(&self, id: &Id) -> Result<Arc<Peer>, Error> {
+        let id = *id;
+        let ctok = self.ctok.child_token();
+        let mut result = Err(Error::LimitExceeded);
+        let mut evicted = None;
+        let created = self.peers.send_if_modified(|m| {
+            if let Some(peer) = m.get(&id) {
+                result = Ok(peer.clone());
+                return false;
+            }
+            if self.tasks.is_full() {
+                return false;
+            }
+            if m.len() >= self.limits.max_peers {
+                let Some(idle) = Self::idle(m) else {
+                    return false;
+                };
+                evicted = m.remove(&idle);
+            }
+            let peer = Peer::new(
+                id,
+                ctok.clone(),
+                self.limits.max_links_per_peer,
+                self.blocklists.subscribe(),
+                self.tasks.clone(),
+            );
+            m.insert(id, peer.clone());
+            result = Ok(peer);
+            true
+        });
+        if let Some(peer) = evicted {
+            log::debug!("Evicting idle peer {}", peer.id());
+            peer.token().cancel();
+        }
+        let peer = result?;
+        if !created {
+            return Ok(peer);
+        }
+        self.tasks.spawn({
             let peers = self.peers.clone();
-            self.peers.send_modify(|m| {
-                m.insert(id, peer.clone());
-            });
-            tokio::spawn(async move {
+            let peer = peer.clone();
+            async move {
                 ctok.cancelled().await;
-                peers.send_modify(|m| {
-                    m.remove(&id);
+                peer.clear();
+                peers.send_if_modified(|m| match m.get(&id) {
+                    Some(p) if Arc::ptr_eq(p, &peer) => m.remove(&id).is_some(),
+                    _ => false,
                 });
-            });
-            peer
+            }
+        });
+        Ok(peer)
+    }
+
+    /// Connect to the peer `id` at `addr` from `node` (see [Peer::connect])
+    ///
+    /// Fails with [Error::LimitExceeded] if this would exceed the [Limits].
+    pub fn connect(&self, id: &Id, node: &Arc<Node>, addr: &SocketAddrV6) -> Result<Arc<Link>, Error> {
+        let links = self.peers.borrow().values().map(|p| p.links().len()).sum::<usize>();
+        if links >= self.limits.max_links {
+            return Err(Error::LimitExceeded);
         }
+        self.get(id)?.connect(node, addr)
+    }
+
+    /// Check whether `ip` is on any of the [Blocklists] (counting the hit)
+    pub fn is_blocked(&self, ip: &Ipv6Addr) -> bool {
+        self.blocklists.borrow().blocks(ip)
+    }
+
+    pub(crate) fn tasks(&self) -> &Tasks {
+        &self.tasks
+    }
+
+    pub fn blocklists(&self) -> Arc<Blocklists> {
+        self.blocklists.borrow().clone()
+    }
+
+    /// Replace the [Blocklists] and terminate all links to addresses now blocked
+    pub fn set_blocklists(&self, lists: Blocklists) {
+        let lists = Arc::new(lists);
+        self.blocklists.send_replace(lists.clone());
+        for peer in self.peers.borrow().values() {
+            for link in peer.links().values().filter(|l| lists.contains(l.addr().ip())) {
+                link.token().cancel();
+            }
+        }
+    }
+
+    /// Find the least recently active peer idle for [PEER_IDLE_TIMEOUT]
+    fn idle(peers: &BTreeMap<Id, Arc<Peer>>) -> Option<Id> {
+        let idle = peers.values().filter(|p| p.last_active().is_none_or(|t| t.elapsed() > PEER_IDLE_TIMEOUT));
+        idle.min_by_key(|p| p.last_active()).map(|p| *p.id())
     }
 
     pub fn borrow(&self) -> impl Deref<Target = BTreeMap<Id, Arc<Peer>>> + '_ {
@@ -55,3 +144,81 @@ impl Peers {
         &self.ctok
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers(max_peers: usize) -> Peers {
+        Peers::new(CancellationToken::new(), Limits { max_peers, ..Limits::default() })
+    }
+
+    #[tokio::test]
+    async fn evicts_idle_peer_when_full() {
+        let peers = peers(2);
+        let (a, b, c) = (Id::random(), Id::random(), Id::random());
+        let pa = peers.get(&a).unwrap();
+        peers.get(&b).unwrap();
+        assert!(Arc::ptr_eq(&pa, &peers.get(&a).unwrap()));
+        peers.get(&c).unwrap();
+        assert_eq!(peers.borrow().len(), 2);
+        assert!(peers.borrow().contains_key(&c));
+        assert_eq!([a, b].iter().filter(|id| !peers.borrow().contains_key(id)).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn evicted_peer_does_not_remove_successor() {
+        let peers = peers(1);
+        let (a, b) = (Id::random(), Id::random());
+        let pa = peers.get(&a).unwrap();
+        peers.get(&b).unwrap();
+        assert!(pa.token().is_cancelled());
+        let pa2 = peers.get(&a).unwrap();
+        tokio::task::yield_now().await;
+        assert!(Arc::ptr_eq(&pa2, &peers.borrow()[&a]));
+    }
+
+    #[tokio::test]
+    async fn internal_blocklist_checks_do_not_count_hits() {
+        use crate::{Blocklist, util::test};
+        let peers = peers(2048);
+        let node = test::node(Id::random(), &peers);
+        let (a, b) = ("[::1]:1".parse().unwrap(), "[::1]:2".parse().unwrap());
+        let link = peers.connect(&Id::random(), &node, &a).unwrap();
+        peers.set_blocklists(Blocklists::new(vec![Blocklist::parse("lo", "::1")]));
+        assert!(link.token().is_cancelled());
+        assert!(matches!(peers.connect(&Id::random(), &node, &b), Err(Error::Blocked)));
+        assert_eq!(peers.blocklists().hits(), 0);
+        assert!(peers.is_blocked(b.ip()));
+        assert_eq!(peers.blocklists().hits(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_gets_create_one_peer() {
+        let peers = Arc::new(peers(2048));
+        let id = Id::random();
+        let mut gets = tokio::task::JoinSet::new();
+        for _ in 0..16 {
+            let peers = peers.clone();
+            gets.spawn(async move { peers.get(&id).unwrap() });
+        }
+        for peer in gets.join_all().await {
+            assert!(Arc::ptr_eq(&peer, &peers.borrow()[&id]));
+        }
+        tokio::task::yield_now().await;
+        assert_eq!(peers.tasks().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_peers_beyond_task_limit() {
+        let peers = Peers::new(CancellationToken::new(), Limits { max_tasks: 1, ..Limits::default() });
+        let pa = peers.get(&Id::random()).unwrap();
+        assert_eq!(peers.tasks().count(), 1);
+        assert!(matches!(peers.get(&Id::random()), Err(Error::LimitExceeded)));
+        assert!(Arc::ptr_eq(&pa, &peers.get(pa.id()).unwrap()));
+        pa.token().cancel();
+        tokio::task::yield_now().await;
+        assert_eq!(peers.tasks().count(), 0);
+        assert!(peers.get(&Id::random()).is_ok());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::constants::*;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io;
+use std::net::{SocketAddr, SocketAddrV6};
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+/// A name resolver used for seed host names
+///
+/// This is an extension point mostly for testing; use [SystemResolver] otherwise.
+pub trait Resolve: Send + Sync + 'static {
+    fn resolve(&self, host: &str, port: u16) -> impl Future<Output = io::Result<Vec<SocketAddr>>> + Send;
+}
+
+/// [Resolve] using the resolver of the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok(tokio::net::lookup_host((host, port)).await?.collect())
+    }
+}
+
+/// Resolve a list of seeds (`host:port` or `[addr]:port`) to socket addresses
+///
+/// The resolution runs in the background and is repeated whenever the seed
+/// list changes or [SEEDS_RESOLVE_INTERVAL] has elapsed. If a host name fails
+/// to resolve, the addresses of the last successful resolution are used. The
+/// task terminates when the returned receiver (and all its clones) is dropped.
+pub fn resolve_seeds<R: Resolve>(
+    resolver: R,
+    seeds: watch::Receiver<Vec<String>>,
+) -> watch::Receiver<Vec<SocketAddrV6>> {
+    let (addrs_, addrs) = watch::channel(Vec::new());
+    tokio::spawn(async move {
+        let mut seeds = seeds;
+        let mut cache = BTreeMap::new();
+        loop {
+            let list = seeds.borrow_and_update().clone();
+            let resolved = resolve_all(&resolver, &list, &mut cache).await;
+            addrs_.send_if_modified(|a| {
+                let modified = a != &resolved;
+                *a = resolved;
+                modified
+            });
+            tokio::select! {
+                _ = sleep(SEEDS_RESOLVE_INTERVAL) => {}
+                Ok(()) = seeds.changed() => {}
+                _ = addrs_.closed() => {
+                    break;
+                }
+            }
+        }
+    });
+    addrs
+}
+
+/// Resolve all seeds, falling back to the cached addresses on failure
+///
+/// Only IPv6 addresses are returned as the DHT does not operate on IPv4 (yet).
+async fn resolve_all<R: Resolve>(
+    resolver: &R,
+    list: &[String],
+    cache: &mut BTreeMap<String, Vec<SocketAddrV6>>,
+) -> Vec<SocketAddrV6> {
+    let mut addrs = Vec::new();
+    cache.retain(|k, _| list.contains(k));
+    for seed in list {
+        let resolved = match seed.parse::<SocketAddr>() {
+            Ok(addr) => Ok(vec![addr]),
+            Err(_) => match seed.rsplit_once(':').map(|(h, p)| (h, p.parse::<u16>())) {
+                Some((host, Ok(port))) => resolver.resolve(host, port).await,
+                _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "expected host:port")),
+            },
+        };
+        let resolved = match resolved {
+            Ok(v) => {
+                let v6 = v.into_iter().filter_map(|a| match a {
+                    SocketAddr::V6(a) => Some(a),
+                    SocketAddr::V4(_) => None,
+                });
+                let v6 = v6.collect::<Vec<_>>();
+                cache.insert(seed.clone(), v6.clone());
+                v6
+            }
+            Err(e) => {
+                log::warn!("Failed to resolve seed {}: {}", seed, e);
+                cache.get(seed).cloned().unwrap_or_default()
+            }
+        };
+        for addr in resolved {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A [Resolve] stand-in answering from a mutable table
+    #[derive(Clone, Default)]
+    struct StubResolver(Arc<Mutex<BTreeMap<String, Vec<SocketAddr>>>>);
+
+    impl StubResolver {
+        fn set(&self, host: &str, addrs: &[&str]) {
+            let addrs = addrs.iter().map(|a| a.parse().unwrap()).collect();
+            self.0.lock().unwrap().insert(host.to_string(), addrs);
+        }
+
+        fn unset(&self, host: &str) {
+            self.0.lock().unwrap().remove(host);
+        }
+    }
+
+    impl Resolve for StubResolver {
+        async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+            let m = self.0.lock().unwrap();
+            let v = m.get(host).ok_or(io::Error::new(io::ErrorKind::NotFound, "NXDOMAIN"))?;
+            Ok(v.iter().map(|a| SocketAddr::new(a.ip(), port)).collect())
+        }
+    }
+
+    fn addrs(v: &[&str]) -> Vec<SocketAddrV6> {
+        v.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    async fn next(rx: &mut watch::Receiver<Vec<SocketAddrV6>>) -> Vec<SocketAddrV6> {
+        rx.changed().await.unwrap();
+        rx.borrow_and_update().clone()
+    }
+
+    #[tokio::test]
+    async fn resolves_hostnames_and_literals() {
+        let resolver = StubResolver::default();
+        resolver.set("dht.example", &["[2001:db8::1]:0", "192.0.2.1:0", "[2001:db8::2]:0"]);
+        let seeds = vec!["[2001:db8::5]:6881".to_string(), "dht.example:6881".to_string()];
+        let (_tx, rx) = watch::channel(seeds);
+        let mut addrs_ = resolve_seeds(resolver, rx);
+        let expected = addrs(&["[2001:db8::5]:6881", "[2001:db8::1]:6881", "[2001:db8::2]:6881"]);
+        assert_eq!(next(&mut addrs_).await, expected);
+    }
+
+    #[tokio::test]
+    async fn re_resolves_and_keeps_last_known_addresses() {
+        let resolver = StubResolver::default();
+        resolver.set("dht.example", &["[2001:db8::1]:0"]);
+        let (tx, rx) = watch::channel(vec!["dht.example:6881".to_string(), "invalid".to_string()]);
+        let mut addrs_ = resolve_seeds(resolver.clone(), rx);
+        assert_eq!(next(&mut addrs_).await, addrs(&["[2001:db8::1]:6881"]));
+
+        resolver.set("dht.example", &["[2001:db8::3]:0"]);
+        tx.send_modify(|_| ());
+        assert_eq!(next(&mut addrs_).await, addrs(&["[2001:db8::3]:6881"]));
+
+        resolver.unset("dht.example");
+        tx.send_modify(|v| v.push("[2001:db8::4]:6881".to_string()));
+        assert_eq!(next(&mut addrs_).await, addrs(&["[2001:db8::3]:6881", "[2001:db8::4]:6881"]));
+    }
+}
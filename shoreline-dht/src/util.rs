@@ -5,32 +5,33 @@ pub fn check(b: bool) -> Option<()> {
     if b { Some(()) } else { None }
 }
 
-pub fn socket() -> UdpSocket {
-    use socket2::{Domain, Protocol, Socket, Type};
-    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-    let _ = socket.set_nonblocking(true);
-    UdpSocket::from_std(socket.into()).unwrap()
-}
-
 pub fn socket_bound(bind: SocketAddrV6) -> Result<UdpSocket, std::io::Error> {
     use socket2::{Domain, Protocol, Socket, Type};
     let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
     socket.set_only_v6(true)?;
     socket.set_reuse_address(true)?;
-    socket.set_reuse_port(true)?;
     socket.bind(&bind.into())?;
     socket.set_nonblocking(true)?;
     Ok(UdpSocket::from_std(socket.into())?)
 }
 
-pub fn socket_connected(bind: &SocketAddrV6, conn: &SocketAddrV6) -> Result<UdpSocket, std::io::Error> {
-    use socket2::{Domain, Protocol, Socket, Type};
-    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
-    socket.set_only_v6(true)?;
-    socket.set_reuse_address(true)?;
-    socket.set_reuse_port(true)?;
-    socket.bind(&(*bind).into())?;
-    socket.connect(&(*conn).into())?;
-    socket.set_nonblocking(true)?;
-    Ok(UdpSocket::from_std(socket.into())?)
+/// Fixtures shared by the tests and fuzz targets
+#[cfg(any(test, fuzzing))]
+pub mod test {
+    use crate::bootstrap::Bootstrap;
+    use crate::{Id, Node, Peers, SocketOptions};
+    use std::collections::BTreeMap;
+    use std::net::{Ipv6Addr, SocketAddrV6};
+    use std::sync::Arc;
+    use tokio::sync::watch;
+
+    /// Create a node with the given [Id] on a free loopback port (without seeds or siblings)
+    pub fn node(id: Id, peers: &Peers) -> Arc<Node> {
+        let sock = std::net::UdpSocket::bind("[::1]:0").unwrap();
+        let addr = SocketAddrV6::new(Ipv6Addr::LOCALHOST, sock.local_addr().unwrap().port(), 0, 0);
+        drop(sock);
+        let boot = Bootstrap::new(watch::channel(vec![]).1);
+        let siblings = watch::channel(BTreeMap::new()).1;
+        Node::new(id, "lo".into(), addr, peers.clone(), boot, siblings, &SocketOptions::default()).unwrap()
+    }
 }
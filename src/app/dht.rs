@@ -3,7 +3,7 @@ use eframe::egui;
 use egui::*;
 use egui_extras::{Column, TableBuilder};
 use human_bytes::human_bytes;
-use shoreline_dht::{DHT, Link, Node, Status, TIMEOUT_INIT, TIMEOUT_TOTAL};
+use shoreline_dht::{BootstrapState, DHT, Link, Node, Reachability, Status, TIMEOUT_INIT, TIMEOUT_TOTAL};
 use std::sync::Arc;
 
 pub struct DhtApp {
@@ -20,6 +20,7 @@ impl DhtApp {
 
     pub const TEXT_ALL_INTERFACES: &'static str = "All";
     pub const TEXT_NO_INTERFACES: &'static str = "No suitable interfaces/addresses found";
+    pub const TEXT_CONNECTING: &'static str = "Connecting\u{2026}";
 
     pub fn new(dht: Arc<DHT>, mmdb: MMDB) -> Self {
         Self { dht, mmdb, interface: None }
@@ -37,9 +38,12 @@ impl DhtApp {
     pub fn paint_empty(&mut self, ctx: &egui::Context) {
         self.interface = None;
         CentralPanel::default().show(ctx, |ui| {
-            ui.centered_and_justified(|ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(ui.available_height() / 2.0 - Self::HEIGHT_HEADER);
                 let text = RichText::new(Self::TEXT_NO_INTERFACES).color(Color32::LIGHT_GRAY).heading();
                 ui.add(Label::new(text));
+                let text = RichText::new(self.dht.policy().to_string()).color(Color32::GRAY);
+                ui.add(Label::new(text));
             });
         });
     }
@@ -66,6 +70,28 @@ impl DhtApp {
                         self.interface = Some(interface.clone());
                     }
                 }
+                ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                    ui.add_space(5.0);
+                    ui.label(RichText::new(self.dht.policy().to_string()).color(Color32::GRAY).small())
+                        .on_hover_text(self.dht.resource_usage().to_string());
+                    let blocklists = self.dht.blocklists();
+                    if blocklists.iter().next().is_some() {
+                        let text = format!("\u{26D4} {}", blocklists.hits());
+                        let lists = blocklists.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+                        ui.label(RichText::new(text).color(Color32::GRAY).small())
+                            .on_hover_text(format!("Blocklists\n{}", lists.join("\n")));
+                    }
+                    if !self.dht.is_bootstrapped() {
+                        let (queried, responded) = self
+                            .dht
+                            .nodes()
+                            .values()
+                            .map(|n| n.stat())
+                            .fold((0, 0), |(q, r), s| (q + s.queried, r + s.responded));
+                        let text = format!("{} ({} queried, {} responded)", Self::TEXT_CONNECTING, queried, responded);
+                        ui.label(RichText::new(text).color(Color32::YELLOW));
+                    }
+                });
             });
             ui.add_space(1.0);
         });
@@ -165,10 +191,27 @@ impl DhtApp {
                                 } else {
                                     Some(Color32::DARK_GRAY.gamma_multiply(0.5).additive())
                                 };
-                                ui.label(RichText::new(self.dht.id().to_string()).monospace());
+                                ui.label(RichText::new(node.id().to_string()).monospace()).on_hover_text(format!(
+                                    "Host ID: {}\nContact: {}",
+                                    self.dht.id(),
+                                    self.dht.id().to_base32()
+                                ));
                                 ui.add_space(0.0);
                             });
-                            row.col(|__| {});
+                            row.col(|ui| {
+                                ui.with_layout(center, |ui| {
+                                    let (icon, color) = match stat.reachability {
+                                        Reachability::Open => ("\u{2714}", Color32::GREEN),
+                                        Reachability::Filtered => ("\u{26D4}", Color32::RED),
+                                        Reachability::Unknown => ("?", Color32::GRAY),
+                                    };
+                                    let text = format!(
+                                        "Reachability: {} ({} unsolicited queries)",
+                                        stat.reachability, stat.unsolicited
+                                    );
+                                    ui.label(RichText::new(icon).color(color)).on_hover_text(text);
+                                });
+                            });
                             row.col(|ui| {
                                 ui.with_layout(center, |ui| {
                                     ui.label(self.mmdb.lookup_iso(*node.addr().ip()).unwrap_or_default());
@@ -182,7 +225,17 @@ impl DhtApp {
                             });
                             row.col(|ui| {
                                 ui.with_layout(right, |ui| {
-                                    ui.label(human_bytes(stat.rx_bytes as f64));
+                                    ui.label(human_bytes(stat.rx_bytes as f64)).on_hover_text(format!(
+                                        "Overload: {} datagrams dropped, {} not sent, {} commands rejected, {} lookups shed\n\
+                                         Abuse: {} datagrams throttled, {} from banned sources ({} bans)",
+                                        stat.dropped,
+                                        stat.unsent,
+                                        stat.rejected,
+                                        stat.shed,
+                                        stat.throttled,
+                                        stat.banned,
+                                        stat.bans
+                                    ));
                                 });
                             });
                             row.col(|ui| {
@@ -193,10 +246,34 @@ impl DhtApp {
                                 ui.label(node.name());
                             });
                             row.col(|ui| {
-                                ui.label(node.addr().to_string());
+                                let label = match stat.external.filter(|a| a != node.addr()) {
+                                    Some(ext) => ui.label(format!("{} (external {})", node.addr(), ext)),
+                                    None => ui.label(node.addr().to_string()),
+                                };
+                                label.on_hover_text(format!(
+                                    "Port mapping: {}\nID changes: {} nodes migrated",
+                                    stat.mapping, stat.migrated
+                                ));
                             });
                             row.col(|ui| {
-                                ui.label(stat.error.map(|e| e.to_string()).unwrap_or_default());
+                                if !stat.clusters.is_empty() {
+                                    let text = format!("\u{26A0} {} clusters", stat.clusters.len());
+                                    let clusters = stat.clusters.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+                                    let hover = format!(
+                                        "Suspected Sybil clusters ({} nodes refused):\n{}",
+                                        stat.crowded,
+                                        clusters.join("\n")
+                                    );
+                                    ui.label(RichText::new(text).color(Color32::YELLOW)).on_hover_text(hover);
+                                }
+                                ui.label(match (stat.error, stat.bootstrap) {
+                                    (Some(e), _) => e.to_string(),
+                                    (None, BootstrapState::Done) => String::new(),
+                                    (None, state) => format!(
+                                        "Bootstrap: {} ({} queried, {} responded, {} in table)",
+                                        state, stat.queried, stat.responded, stat.table
+                                    ),
+                                });
                             });
                         });
                     }
@@ -204,14 +281,15 @@ impl DhtApp {
                     for peer in peers {
                         for (i, link) in peer.links().values().filter(filter_link).enumerate() {
                             let stat = { link.stat().borrow().clone() };
-                            let rtt = stat.rtt.map(|x| x.as_secs_f32()).unwrap_or(5.0) * 1000.0;
+                            let rtt = stat.srtt.map(|x| (x + stat.rttvar).as_secs_f32()).unwrap_or(5.0) * 1000.0;
                             let rtt = rtt.log10();
                             let rtt = (rtt / 3.0).min(1.0).max(0.0);
 
                             body.row(18.0, |mut row| {
                                 row.col(|ui| {
                                     let bg_color = match stat.status {
-                                        Status::Good => Color32::GREEN.gamma_multiply(1.0 - rtt),
+                                        Status::Good => Color32::GREEN.gamma_multiply((1.0 - rtt) * (1.0 - stat.loss())),
+                                        Status::Questionable => Color32::YELLOW.gamma_multiply(0.5),
                                         Status::Term => Color32::RED.gamma_multiply(0.5),
                                         Status::Init => {
                                             let t = stat.rx_last.elapsed().as_secs_f32();
@@ -235,11 +313,24 @@ impl DhtApp {
                                         Some(Color32::DARK_GRAY.gamma_multiply(0.5).additive())
                                     };
                                     paint_bg(ui, bg_color);
-                                    ui.label(RichText::new(link.peer().id().to_string()).monospace());
+                                    let peer = link.peer();
+                                    let mut text = RichText::new(peer.id().to_string()).monospace();
+                                    let mut hover = vec![];
+                                    if let Some(id) = peer.previous_id() {
+                                        hover.push(format!("Previously {}", id));
+                                    }
+                                    if peer.is_collision() {
+                                        hover.push("ID seen from unrelated networks (collision)".to_string());
+                                        text = text.color(Color32::YELLOW);
+                                    }
+                                    let label = ui.label(text);
+                                    if !hover.is_empty() {
+                                        label.on_hover_text(hover.join("\n"));
+                                    }
                                 });
                                 row.col(|ui| {
                                     ui.with_layout(center, |ui| {
-                                        ui.label(link.peer().id().distance(self.dht.id()).to_string());
+                                        ui.label(link.peer().id().distance(link.node().id()).to_string());
                                     });
                                 });
                                 row.col(|ui| {
@@ -249,11 +340,25 @@ impl DhtApp {
                                 });
                                 row.col(|ui| {
                                     ui.with_layout(right, |ui| {
-                                        ui.label(if matches!(stat.status, Status::Init | Status::Fail | Status::Term) {
+                                        let label = ui.label(if matches!(stat.status, Status::Init | Status::Questionable | Status::Fail | Status::Term) {
                                             stat.rx_last.elapsed().as_secs().to_string() + " s"
                                         } else {
-                                            stat.rtt.map(|x| format!("{} ms", x.as_millis())).unwrap_or_default()
+                                            stat.srtt.map(|x| format!("{} ms", x.as_millis())).unwrap_or_default()
                                         });
+                                        if let (Some(srtt), Some(min), Some(max)) = (stat.srtt, stat.rtt_min, stat.rtt_max) {
+                                            label.on_hover_text(format!(
+                                                "RTT min/avg/max: {}/{}/{} ms, jitter {} ms\n\
+                                                 RTO {} ms, loss {:.1}% ({} of {} queries)",
+                                                min.as_millis(),
+                                                srtt.as_millis(),
+                                                max.as_millis(),
+                                                stat.rttvar.as_millis(),
+                                                stat.rto().as_millis(),
+                                                stat.loss() * 100.0,
+                                                stat.timeouts,
+                                                stat.queries
+                                            ));
+                                        }
                                     });
                                 });
                                 row.col(|ui| {
@@ -263,7 +368,8 @@ impl DhtApp {
                                 });
                                 row.col(|ui| {
                                     ui.with_layout(right, |ui| {
-                                        ui.label(human_bytes(stat.rx_bytes as f64));
+                                        ui.label(human_bytes(stat.rx_bytes as f64))
+                                            .on_hover_text(format!("Overload: {} queries shed", stat.shed));
                                     });
                                 });
                                 row.col(|ui| {
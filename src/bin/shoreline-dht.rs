@@ -1,22 +1,40 @@
-use shoreline::{config::Config, SEEDS};
-use shoreline_dht::DHT;
-use tokio::sync::watch;
+use shoreline::{blocklists::BlocklistDir, cache::NodeCache, config::Config, util::watch_map};
+use shoreline_dht::{DHT, SystemResolver, resolve_seeds};
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::Builder::new().filter_level(log::LevelFilter::Info).init();
 
-    let seeds = watch::channel(
-        SEEDS.iter()
-            .filter_map(|s| s.parse().ok())
-            .collect::<Vec<_>>(),
-    ).1;
-    let config = Config::load().await.map_err(|e| e.to_string())?;
-    let dht = DHT::new(config.dht.node_id, config.dht.bind_port, seeds);
+    let dir = Config::dir().await.map_err(|e| e.to_string())?;
+    let config = Config::watch().await.map_err(|e| e.to_string())?;
+    let seeds = resolve_seeds(SystemResolver, watch_map(config.clone(), |c| c.dht.seeds()));
+    let config = config.borrow().clone();
+    let dht = Arc::new(DHT::new(
+        config.dht.node_id,
+        config.dht.bind_port,
+        config.dht.interfaces.policy(),
+        config.dht.port_mapping.mapping(),
+        config.dht.socket.options(),
+        config.dht.limits.limits(),
+        seeds,
+    ));
+    dht.restore(NodeCache::load(&dir).await.unwrap_or_default());
+    BlocklistDir::watch(dir.clone(), dht.clone());
+
+    let bootstrapped = dht.bootstrapped();
+    tokio::spawn(async move {
+        bootstrapped.await;
+        log::info!("DHT is bootstrapped");
+    });
 
     loop {
-        let len = dht.peers().len();
-        log::info!("DHT is running ({} peers)...", len);
+        let usage = dht.resource_usage();
+        let state = if dht.is_bootstrapped() { "running" } else { "bootstrapping" };
+        log::info!("DHT is {} ({})...", state, usage);
+        if let Err(e) = NodeCache::save(&dir, dht.snapshot()).await {
+            log::warn!("Failed to save node cache: {}", e);
+        }
         tokio::time::sleep(std::time::Duration::from_secs(60)).await;
     }
 }
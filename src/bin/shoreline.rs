@@ -1,7 +1,7 @@
 use eframe::egui;
 use shoreline::app::MainApp;
-use shoreline::{config::Config, mmdb::MMDB, SEEDS};
-use shoreline_dht::DHT;
+use shoreline::{blocklists::BlocklistDir, cache::NodeCache, config::Config, mmdb::MMDB, util::watch_map};
+use shoreline_dht::{DHT, SystemResolver, resolve_seeds};
 use std::sync::Arc;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -11,14 +11,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let node: Result<(Arc<DHT>, MMDB), String> = rt.block_on(async {
         let dir = Config::dir().await.map_err(|e| e.to_string())?;
-        let config = Config::load().await.map_err(|e| e.to_string())?;
-        let seeds = tokio::sync::watch::channel(
-            SEEDS.iter()
-                .filter_map(|s| s.parse().ok())
-                .collect::<Vec<_>>(),
-        ).1;
-        let dht = DHT::new(config.dht.node_id, config.dht.bind_port, seeds);
+        let config = Config::watch().await.map_err(|e| e.to_string())?;
+        let seeds = resolve_seeds(SystemResolver, watch_map(config.clone(), |c| c.dht.seeds()));
+        let config = config.borrow().clone();
+        let dht = DHT::new(
+            config.dht.node_id,
+            config.dht.bind_port,
+            config.dht.interfaces.policy(),
+            config.dht.port_mapping.mapping(),
+            config.dht.socket.options(),
+            config.dht.limits.limits(),
+            seeds,
+        );
+        dht.restore(NodeCache::load(&dir).await.unwrap_or_default());
         let dht = Arc::new(dht);
+        BlocklistDir::watch(dir.clone(), dht.clone());
+        tokio::spawn({
+            let dht = dht.clone();
+            let dir = dir.clone();
+            async move {
+                let mut intvl = tokio::time::interval(std::time::Duration::from_secs(60));
+                loop {
+                    intvl.tick().await;
+                    if let Err(e) = NodeCache::save(&dir, dht.snapshot()).await {
+                        log::warn!("Failed to save node cache: {}", e);
+                    }
+                }
+            }
+        });
         let mmdb = MMDB::new(dir.join("dbip-country.mmdb"));
         Ok((dht, mmdb))
     });
@@ -0,0 +1,67 @@
+use crate::config::Config;
+use shoreline_dht::{Blocklist, Blocklists, DHT};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Blocklist files in the `blocklists` subdirectory of the config directory
+///
+/// Every file is parsed as a [Blocklist] (P2P, ipfilter.dat or CIDR format).
+pub struct BlocklistDir;
+
+impl BlocklistDir {
+    pub const DIR: &'static str = "blocklists";
+
+    pub async fn load(dir: &Path) -> Result<Blocklists, Error> {
+        let mut lists = vec![];
+        for path in Self::files(dir).into_keys() {
+            let content = tokio::fs::read(&path).await?;
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let list = Blocklist::parse(name, &String::from_utf8_lossy(&content));
+            log::info!("Loaded blocklist {} ({} ranges, {} lines skipped)", path.display(), list.len(), list.skipped());
+            lists.push(list);
+        }
+        Ok(Blocklists::new(lists))
+    }
+
+    /// Load the blocklists into `dht` and reload them whenever a file changes
+    ///
+    /// The directory is checked every [Config::RELOAD_INTERVAL]. Lists that fail
+    /// to load are logged and the previous ones stay in effect until a later
+    /// check loads them successfully.
+    pub fn watch(dir: PathBuf, dht: Arc<DHT>) {
+        let _ = std::fs::create_dir_all(dir.join(Self::DIR));
+        tokio::spawn(async move {
+            let mut last = BTreeMap::new();
+            let mut intvl = tokio::time::interval(Config::RELOAD_INTERVAL);
+            loop {
+                intvl.tick().await;
+                let files = Self::files(&dir);
+                if files != last {
+                    match Self::load(&dir).await {
+                        Ok(lists) => {
+                            dht.set_blocklists(lists);
+                            last = files;
+                        }
+                        Err(e) => log::warn!("Failed to load blocklists: {}", e),
+                    }
+                }
+            }
+        });
+    }
+
+    /// Get all files with their modification time
+    fn files(dir: &Path) -> BTreeMap<PathBuf, Option<SystemTime>> {
+        let Ok(entries) = std::fs::read_dir(dir.join(Self::DIR)) else {
+            return BTreeMap::new();
+        };
+        entries
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+            .map(|e| (e.path(), e.metadata().and_then(|m| m.modified()).ok()))
+            .collect()
+    }
+}
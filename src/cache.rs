@@ -0,0 +1,48 @@
+use shoreline_dht::{Info, Infos};
+use std::path::Path;
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Persisted nodes used to bootstrap the DHT when the seeds are unresponsive
+///
+/// The nodes are stored in compact `nodes6` encoding (38 bytes per node).
+pub struct NodeCache;
+
+impl NodeCache {
+    pub const FILE: &'static str = "nodes.dat";
+
+    pub async fn load(dir: &Path) -> Result<Vec<Info>, Error> {
+        let path = dir.join(Self::FILE);
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let content = tokio::fs::read(path).await?;
+        let infos = Infos::decode(&content).ok_or("Invalid node cache")?;
+        Ok(infos.into())
+    }
+
+    /// Save the given nodes (unless empty in order not to lose the previous ones)
+    pub async fn save(dir: &Path, infos: Vec<Info>) -> Result<(), Error> {
+        if !infos.is_empty() {
+            tokio::fs::write(dir.join(Self::FILE), Infos::from(infos).encode()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trips_nodes() {
+        let dir = std::env::temp_dir().join(format!("shoreline-cache-{}", rand::random::<u64>()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        assert!(NodeCache::load(&dir).await.unwrap().is_empty());
+        let infos = vec![Info::new(shoreline_dht::Id::random(), "[2001:db8::1]:6881".parse().unwrap())];
+        NodeCache::save(&dir, infos.clone()).await.unwrap();
+        NodeCache::save(&dir, vec![]).await.unwrap();
+        assert_eq!(NodeCache::load(&dir).await.unwrap(), infos);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
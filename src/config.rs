@@ -1,24 +1,156 @@
 use serde::{Deserialize, Serialize};
-use shoreline_dht::Id;
-use std::path::PathBuf;
+use crate::SEEDS;
+use shoreline_dht::{Id, Limits, Policy, PortMapping, SocketOptions};
+use std::net::Ipv6Addr;
+use std::path::{Path, PathBuf};
+use tokio::sync::watch;
+use tokio::time::Duration;
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub dht: DhtConfig,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DhtConfig {
     pub node_id: Id,
     pub bind_port: u16,
+    /// Bootstrap nodes (`host:port` or `[addr]:port`); [SEEDS] if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seeds: Option<Vec<String>>,
+    #[serde(default)]
+    pub interfaces: InterfacesConfig,
+    #[serde(default)]
+    pub port_mapping: PortMappingConfig,
+    #[serde(default)]
+    pub socket: SocketConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+}
+
+impl DhtConfig {
+    /// Get the configured seeds or the default ones
+    pub fn seeds(&self) -> Vec<String> {
+        match &self.seeds {
+            Some(seeds) => seeds.clone(),
+            None => SEEDS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+/// Interface selection rules; see [Policy]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InterfacesConfig {
+    /// Interface name patterns to use (all if empty), e.g. `["eth*", "wl*"]`
+    pub include: Vec<String>,
+    /// Interface name patterns to never use, e.g. `["docker*", "tun*", "wg*"]`
+    pub exclude: Vec<String>,
+    /// Participate with global unicast addresses
+    pub gua: bool,
+    /// Participate with unique local addresses
+    pub ula: bool,
+}
+
+impl InterfacesConfig {
+    pub fn policy(&self) -> Policy {
+        Policy { include: self.include.clone(), exclude: self.exclude.clone(), gua: self.gua, ula: self.ula }
+    }
+}
+
+impl Default for InterfacesConfig {
+    fn default() -> Self {
+        let policy = Policy::default();
+        Self { include: policy.include, exclude: policy.exclude, gua: policy.gua, ula: policy.ula }
+    }
+}
+
+/// Port mapping via PCP; see [PortMapping]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortMappingConfig {
+    /// Request a mapping/pinhole for each interface address from the gateway
+    pub enabled: bool,
+    /// The PCP server (the interface's default router if absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<Ipv6Addr>,
+}
+
+impl PortMappingConfig {
+    pub fn mapping(&self) -> PortMapping {
+        PortMapping { enabled: self.enabled, gateway: self.gateway }
+    }
+}
+
+/// Socket buffer sizes in bytes and NAT keepalive; see [SocketOptions]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SocketConfig {
+    /// `SO_RCVBUF` of the node sockets (the system default if absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recv_buffer_size: Option<usize>,
+    /// `SO_SNDBUF` of the node sockets (the system default if absent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_buffer_size: Option<usize>,
+    /// Ping the peers in the routing table regularly while behind a NAT or firewall
+    ///
+    /// Off by default, unlike earlier versions, which pinged every peer every
+    /// 25 seconds; switch it on when running behind a NAT or firewall.
+    pub nat_keepalive: bool,
+}
+
+impl SocketConfig {
+    pub fn options(&self) -> SocketOptions {
+        SocketOptions {
+            recv_buffer_size: self.recv_buffer_size,
+            send_buffer_size: self.send_buffer_size,
+            nat_keepalive: self.nat_keepalive,
+        }
+    }
+}
+
+/// Upper bounds on peers, links and their tasks; see [Limits]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_peers: usize,
+    pub max_links: usize,
+    pub max_links_per_peer: usize,
+    pub max_tasks: usize,
+}
+
+impl LimitsConfig {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_peers: self.max_peers,
+            max_links: self.max_links,
+            max_links_per_peer: self.max_links_per_peer,
+            max_tasks: self.max_tasks,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Self {
+            max_peers: limits.max_peers,
+            max_links: limits.max_links,
+            max_links_per_peer: limits.max_links_per_peer,
+            max_tasks: limits.max_tasks,
+        }
+    }
 }
 
 impl Config {
+    pub const FILE: &'static str = "config.toml";
+    pub const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
     pub async fn load() -> Result<Self, Error> {
         let dir = Self::dir().await?;
-        let path = dir.join("config.toml");
+        let path = dir.join(Self::FILE);
 
         if !path.exists() {
             let default = Config::default();
@@ -26,12 +158,50 @@ impl Config {
             tokio::fs::write(path, content).await?;
             Ok(default)
         } else {
-            let content = tokio::fs::read_to_string(path).await?;
-            let config: Config = toml::from_str(&content)?;
-            Ok(config)
+            Self::read(&path).await
         }
     }
 
+    /// Load the config and reload it whenever the file is modified
+    ///
+    /// The file is checked every [Self::RELOAD_INTERVAL]. A modified file that
+    /// fails to parse is logged and otherwise ignored.
+    pub async fn watch() -> Result<watch::Receiver<Self>, Error> {
+        let path = Self::dir().await?.join(Self::FILE);
+        let (config_, config) = watch::channel(Self::load().await?);
+        let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+        let mut last = modified(&path);
+        tokio::spawn(async move {
+            let mut intvl = tokio::time::interval(Self::RELOAD_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = intvl.tick() => {}
+                    _ = config_.closed() => {
+                        break;
+                    }
+                }
+                let m = modified(&path);
+                if m != last {
+                    last = m;
+                    match Self::read(&path).await {
+                        Ok(c) => {
+                            log::info!("Reloaded {}", path.display());
+                            config_.send_replace(c);
+                        }
+                        Err(e) => log::warn!("Failed to reload {}: {}", path.display(), e),
+                    }
+                }
+            }
+        });
+        Ok(config)
+    }
+
+    async fn read(path: &Path) -> Result<Self, Error> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let config: Config = toml::from_str(&content)?;
+        Ok(config)
+    }
+
     pub async fn dir() -> Result<PathBuf, Error> {
         let dir1 = std::env::var("SHORELINE_CONFIG_DIR").ok().map(|x| PathBuf::from(x));
         let dir2 = std::env::current_dir().ok().map(|x| x.join(".shoreline"));
@@ -56,6 +226,16 @@ impl Config {
 
 impl Default for Config {
     fn default() -> Self {
-        Self { dht: DhtConfig { node_id: Id::random(), bind_port: 6881 } }
+        Self {
+            dht: DhtConfig {
+                node_id: Id::random(),
+                bind_port: 6881,
+                seeds: None,
+                interfaces: InterfacesConfig::default(),
+                port_mapping: PortMappingConfig::default(),
+                socket: SocketConfig::default(),
+                limits: LimitsConfig::default(),
+            },
+        }
     }
 }
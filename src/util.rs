@@ -1,5 +1,5 @@
 use std::{net::SocketAddrV6, sync::Arc};
-use tokio::{net::UdpSocket, sync::SetOnce};
+use tokio::{net::UdpSocket, sync::SetOnce, sync::watch};
 use tokio::time::{Duration, Instant, Sleep, sleep_until};
 
 #[derive(Debug)]
@@ -21,6 +21,32 @@ impl <T> From<Arc<SetOnce<T>>> for Promise<T> {
     }
 }
 
+/// Derive a watch channel from another by applying `f` to every value
+///
+/// The derived channel is only notified if the mapped value actually changes.
+pub fn watch_map<T, U, F>(mut rx: watch::Receiver<T>, f: F) -> watch::Receiver<U>
+where
+    T: Send + Sync + 'static,
+    U: PartialEq + Send + Sync + 'static,
+    F: Fn(&T) -> U + Send + 'static,
+{
+    let (tx, rx_) = watch::channel(f(&rx.borrow_and_update()));
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Ok(()) = rx.changed() => {
+                    let u = f(&rx.borrow_and_update());
+                    tx.send_if_modified(|x| if *x != u { *x = u; true } else { false });
+                }
+                _ = tx.closed() => {
+                    break;
+                }
+            }
+        }
+    });
+    rx_
+}
+
 pub fn check(b: bool) -> Option<()> {
     if b {
         Some(())